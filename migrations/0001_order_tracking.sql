@@ -0,0 +1,18 @@
+-- Номер заказа для покупателя (TB-YYMMDD-NNNN) и история статусов
+ALTER TABLE orders ADD COLUMN order_number TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_orders_order_number ON orders (order_number);
+
+-- Счётчик заказов за день (по времени Алматы)
+CREATE TABLE IF NOT EXISTS order_counters (
+    day TEXT PRIMARY KEY,
+    seq INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS order_status_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_id INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    note TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_order_status_history_order ON order_status_history (order_id);
@@ -0,0 +1,9 @@
+-- Ключи идемпотентности для повторных POST /api/create-order
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    request_body TEXT NOT NULL,
+    order_id INTEGER,
+    response_json TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at ON idempotency_keys (created_at);
@@ -0,0 +1,7 @@
+-- Промокод в заказе и индексы для фильтров списка заказов в админке
+ALTER TABLE orders ADD COLUMN promo_code TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_orders_created_at ON orders (created_at);
+CREATE INDEX IF NOT EXISTS idx_orders_status_created_at ON orders (status, created_at);
+CREATE INDEX IF NOT EXISTS idx_orders_customer_phone ON orders (customer_phone);
+CREATE INDEX IF NOT EXISTS idx_orders_promo_code ON orders (promo_code);
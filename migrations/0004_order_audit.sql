@@ -0,0 +1,10 @@
+-- Журнал изменений заказа из админки
+CREATE TABLE IF NOT EXISTS order_audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    changes_json TEXT NOT NULL,
+    actor TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_order_audit_log_order ON order_audit_log (order_id);
@@ -0,0 +1,17 @@
+-- Зоны доставки: полигон (GeoJSON) и/или список районов и улиц
+CREATE TABLE IF NOT EXISTS delivery_zones (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    name_kk TEXT,
+    polygon TEXT,
+    districts TEXT,
+    delivery_fee REAL NOT NULL DEFAULT 0,
+    free_delivery_from REAL,
+    min_order REAL NOT NULL DEFAULT 0,
+    is_active INTEGER NOT NULL DEFAULT 1
+);
+
+ALTER TABLE orders ADD COLUMN delivery_zone_id INTEGER;
+ALTER TABLE orders ADD COLUMN delivery_fee REAL NOT NULL DEFAULT 0;
+ALTER TABLE orders ADD COLUMN latitude REAL;
+ALTER TABLE orders ADD COLUMN longitude REAL;
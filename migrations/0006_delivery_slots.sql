@@ -0,0 +1,24 @@
+-- Расписание слотов доставки по дням недели (1 = понедельник ... 7 = воскресенье)
+CREATE TABLE IF NOT EXISTS delivery_slots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    weekday INTEGER NOT NULL,
+    start_time TEXT NOT NULL,
+    end_time TEXT NOT NULL,
+    capacity INTEGER NOT NULL,
+    cutoff_minutes INTEGER NOT NULL DEFAULT 60,
+    is_active INTEGER NOT NULL DEFAULT 1
+);
+
+-- Занятость слотов по датам. CHECK не даёт записать больше заказов, чем вмещает слот:
+-- при переполнении падает весь batch создания заказа
+CREATE TABLE IF NOT EXISTS delivery_slot_bookings (
+    slot_id INTEGER NOT NULL,
+    slot_date TEXT NOT NULL,
+    booked INTEGER NOT NULL DEFAULT 0,
+    capacity INTEGER NOT NULL,
+    PRIMARY KEY (slot_id, slot_date),
+    CHECK (booked <= capacity)
+);
+
+ALTER TABLE orders ADD COLUMN delivery_slot_id INTEGER;
+ALTER TABLE orders ADD COLUMN delivery_date TEXT;
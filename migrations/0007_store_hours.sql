@@ -0,0 +1,17 @@
+-- Часы работы магазина по дням недели (1 = понедельник ... 7 = воскресенье), время местное
+CREATE TABLE IF NOT EXISTS store_hours (
+    weekday INTEGER PRIMARY KEY,
+    open_time TEXT,
+    close_time TEXT,
+    is_closed INTEGER NOT NULL DEFAULT 0
+);
+
+-- Праздники и другие нерабочие дни
+CREATE TABLE IF NOT EXISTS store_closures (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    date TEXT NOT NULL UNIQUE,
+    reason TEXT,
+    reason_kk TEXT
+);
+
+ALTER TABLE orders ADD COLUMN placed_while_closed INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,13 @@
+-- Время создания и изменения (UTC, формат datetime('now')) для товаров, категорий и промокодов.
+-- ALTER TABLE не позволяет DEFAULT (datetime('now')), поэтому заполняем существующие строки отдельно
+ALTER TABLE products ADD COLUMN created_at TEXT;
+ALTER TABLE products ADD COLUMN updated_at TEXT;
+UPDATE products SET created_at = datetime('now'), updated_at = datetime('now') WHERE created_at IS NULL;
+
+ALTER TABLE categories ADD COLUMN created_at TEXT;
+ALTER TABLE categories ADD COLUMN updated_at TEXT;
+UPDATE categories SET created_at = datetime('now'), updated_at = datetime('now') WHERE created_at IS NULL;
+
+ALTER TABLE promocodes ADD COLUMN created_at TEXT;
+ALTER TABLE promocodes ADD COLUMN updated_at TEXT;
+UPDATE promocodes SET created_at = datetime('now'), updated_at = datetime('now') WHERE created_at IS NULL;
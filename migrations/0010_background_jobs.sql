@@ -0,0 +1,28 @@
+-- Дневная статистика продаж (заполняется обработчиком очереди)
+CREATE TABLE IF NOT EXISTS sales_daily (
+    day TEXT PRIMARY KEY,
+    orders_count INTEGER NOT NULL DEFAULT 0,
+    revenue REAL NOT NULL DEFAULT 0,
+    delivery_revenue REAL NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS product_sales_daily (
+    day TEXT NOT NULL,
+    product_id INTEGER NOT NULL,
+    quantity REAL NOT NULL DEFAULT 0,
+    revenue REAL NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, product_id)
+);
+
+-- Заказ уже учтён в статистике (защита от повторной доставки сообщения)
+ALTER TABLE orders ADD COLUMN analytics_recorded_at TEXT;
+
+-- Сообщения, которые не удалось обработать после всех повторов
+CREATE TABLE IF NOT EXISTS queue_dead_letters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue TEXT NOT NULL,
+    message_id TEXT,
+    body TEXT NOT NULL,
+    error TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,5 @@
+-- Порог остатка для оповещений о нехватке товара
+ALTER TABLE products ADD COLUMN low_stock_threshold REAL;
+-- Последнее отправленное оповещение: 'low' или 'out' (NULL — остаток в норме)
+ALTER TABLE products ADD COLUMN stock_alert TEXT;
+CREATE INDEX IF NOT EXISTS idx_products_stock ON products (stock);
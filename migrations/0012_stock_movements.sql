@@ -0,0 +1,14 @@
+-- Журнал движения товара: каждое изменение остатка с причиной
+CREATE TABLE IF NOT EXISTS stock_movements (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    product_id INTEGER NOT NULL,
+    reason TEXT NOT NULL CHECK (reason IN ('sale', 'restock', 'correction', 'cancellation', 'write_off')),
+    delta REAL NOT NULL,
+    balance REAL,
+    actor TEXT,
+    order_id INTEGER,
+    note TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_stock_movements_product ON stock_movements (product_id, id);
+CREATE INDEX IF NOT EXISTS idx_stock_movements_order ON stock_movements (order_id);
@@ -0,0 +1,3 @@
+-- Приёмка товара: поставщик и закупочная цена за единицу
+ALTER TABLE stock_movements ADD COLUMN supplier TEXT;
+ALTER TABLE stock_movements ADD COLUMN unit_cost REAL;
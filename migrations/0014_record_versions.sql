@@ -0,0 +1,3 @@
+-- Версия записи для оптимистичной блокировки правок из админки
+ALTER TABLE products ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE categories ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
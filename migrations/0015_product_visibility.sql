@@ -0,0 +1,2 @@
+-- Скрытые товары не показываются на витрине и недоступны для заказа
+ALTER TABLE products ADD COLUMN is_hidden INTEGER NOT NULL DEFAULT 0;
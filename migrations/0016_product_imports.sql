@@ -0,0 +1,17 @@
+-- История импорта товаров из CSV (включая пробные прогоны)
+CREATE TABLE IF NOT EXISTS product_imports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    file_name TEXT,
+    actor TEXT,
+    dry_run INTEGER NOT NULL DEFAULT 1,
+    committed INTEGER NOT NULL DEFAULT 0,
+    match_by TEXT NOT NULL,
+    total_rows INTEGER NOT NULL DEFAULT 0,
+    created_count INTEGER NOT NULL DEFAULT 0,
+    updated_count INTEGER NOT NULL DEFAULT 0,
+    unchanged_count INTEGER NOT NULL DEFAULT 0,
+    error_count INTEGER NOT NULL DEFAULT 0,
+    -- [{ "line": 3, "errors": ["..."] }]
+    errors_json TEXT NOT NULL DEFAULT '[]',
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
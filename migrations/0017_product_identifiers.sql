@@ -0,0 +1,12 @@
+-- Артикул и штрихкоды для складских сканеров.
+-- Штрихкодов у товара может быть несколько, но каждый принадлежит одному товару
+ALTER TABLE products ADD COLUMN sku TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_products_sku ON products (sku COLLATE NOCASE) WHERE sku IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS product_barcodes (
+    -- EAN-13 (UPC-A с ведущим нулём) или EAN-8
+    barcode TEXT PRIMARY KEY,
+    product_id INTEGER NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_product_barcodes_product ON product_barcodes (product_id);
@@ -0,0 +1,7 @@
+-- Варианты товара (фасовки 0.5 л / 1 л / 2 л): строки products со ссылкой на родителя.
+-- Название, описание и картинки общие — у родителя; у варианта своя цена, старая цена,
+-- остаток, единица и артикул. Вложенность одна: у варианта не бывает своих вариантов
+ALTER TABLE products ADD COLUMN parent_id INTEGER;
+ALTER TABLE products ADD COLUMN variant_label TEXT;
+ALTER TABLE products ADD COLUMN variant_label_kk TEXT;
+CREATE INDEX IF NOT EXISTS idx_products_parent ON products (parent_id);
@@ -0,0 +1,31 @@
+-- Характеристики товаров (бренд, страна, жирность, объём) вместо текста в описании.
+-- Набор характеристик задаётся для категории; код — ключ фильтра: ?attr[brand]=Food Master
+CREATE TABLE IF NOT EXISTS category_attributes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    category_id INTEGER NOT NULL,
+    code TEXT NOT NULL,
+    name TEXT NOT NULL,
+    name_kk TEXT,
+    -- text / number / enum / bool
+    type TEXT NOT NULL DEFAULT 'text',
+    -- JSON-массив допустимых значений для enum
+    options TEXT,
+    -- Единица для number: "%", "л"
+    unit TEXT,
+    sort_order INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    UNIQUE (category_id, code)
+);
+CREATE INDEX IF NOT EXISTS idx_category_attributes_code ON category_attributes (code);
+
+CREATE TABLE IF NOT EXISTS product_attribute_values (
+    product_id INTEGER NOT NULL,
+    attribute_id INTEGER NOT NULL,
+    -- Значение в каноническом виде: число "3.2", bool "true"/"false"
+    value TEXT NOT NULL,
+    -- Для number и bool (1/0) — чтобы "3.20" в фильтре совпало с "3.2"
+    value_num REAL,
+    PRIMARY KEY (product_id, attribute_id)
+);
+CREATE INDEX IF NOT EXISTS idx_product_attribute_values_attribute ON product_attribute_values (attribute_id, value);
@@ -0,0 +1,6 @@
+-- Проверка остатка внутри batch. Если списание не прошло (остатка не хватило),
+-- запрос-страж пишет сюда строку с ok = 0: CHECK не даёт её записать и откатывает весь batch
+CREATE TABLE IF NOT EXISTS stock_guard (
+    ok INTEGER NOT NULL,
+    CONSTRAINT stock_available CHECK (ok = 1)
+);
@@ -0,0 +1,194 @@
+use chrono::{
+    DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use serde::Serializer;
+use std::cell::Cell;
+use worker::Env;
+
+// Формат SQLite datetime('now'): время в базе хранится в UTC
+const DB_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// Воркер однопоточный, поэтому часовой пояс магазина храним в thread_local
+thread_local! {
+    static STORE_TZ: Cell<Tz> = const { Cell::new(chrono_tz::Asia::Almaty) };
+}
+
+// Часовой пояс из переменной STORE_TIMEZONE (по умолчанию Asia/Almaty)
+pub fn configure(env: &Env) {
+    if let Ok(tz) = env.var("STORE_TIMEZONE") {
+        if let Ok(tz) = tz.to_string().parse::<Tz>() {
+            STORE_TZ.with(|cell| cell.set(tz));
+        }
+    }
+}
+
+pub fn store_tz() -> Tz {
+    STORE_TZ.with(|cell| cell.get())
+}
+
+pub fn now_utc() -> DateTime<Utc> {
+    let millis = worker::Date::now().as_millis() as i64;
+    DateTime::from_timestamp_millis(millis).unwrap_or_default()
+}
+
+pub fn now_local() -> DateTime<Tz> {
+    now_utc().with_timezone(&store_tz())
+}
+
+pub fn today() -> NaiveDate {
+    now_local().date_naive()
+}
+
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok()
+}
+
+pub fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M").ok()
+}
+
+// Местные дата и время магазина -> момент времени.
+// При переводе часов берём более раннее значение
+pub fn local_datetime(date: NaiveDate, time: NaiveTime) -> Option<DateTime<Tz>> {
+    store_tz()
+        .from_local_datetime(&NaiveDateTime::new(date, time))
+        .earliest()
+}
+
+// RFC 3339 со смещением пояса магазина, например 2024-09-15T14:03:00+05:00
+pub fn format<T: TimeZone>(dt: &DateTime<T>) -> String {
+    dt.with_timezone(&store_tz())
+        .to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+// Время из базы -> RFC 3339. Непонятные значения отдаём как есть
+pub fn to_rfc3339(value: &str) -> String {
+    if let Ok(dt) = NaiveDateTime::parse_from_str(value.trim(), DB_FORMAT) {
+        return format(&Utc.from_utc_datetime(&dt));
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value.trim()) {
+        return format(&dt);
+    }
+    value.to_string()
+}
+
+// Дата по часовому поясу магазина для времени из базы
+pub fn local_date(value: &str) -> Option<NaiveDate> {
+    let dt = NaiveDateTime::parse_from_str(value.trim(), DB_FORMAT).ok()?;
+    Some(
+        Utc.from_utc_datetime(&dt)
+            .with_timezone(&store_tz())
+            .date_naive(),
+    )
+}
+
+pub fn serialize_timestamp<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_rfc3339(value))
+}
+
+pub fn serialize_opt_timestamp<S: Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.serialize_str(&to_rfc3339(value)),
+        None => serializer.serialize_none(),
+    }
+}
+
+// Граница периода из query-параметра -> UTC в формате базы.
+// Дата без времени считается по часовому поясу магазина: начало дня,
+// а для конца периода (end = true) — начало следующего дня
+pub fn query_bound_utc(value: &str, end: bool) -> Option<String> {
+    let value = value.trim();
+    let instant = match DateTime::parse_from_rfc3339(value) {
+        Ok(dt) => dt.with_timezone(&Utc),
+        Err(_) => {
+            let date = parse_date(value)?;
+            let date = if end { date + Duration::days(1) } else { date };
+            local_datetime(date, NaiveTime::MIN)?.with_timezone(&Utc)
+        }
+    };
+    Some(instant.format(DB_FORMAT).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_with_almaty_offset() {
+        // Записи datetime('now') — UTC без пояса
+        assert_eq!(
+            to_rfc3339("2025-09-15 09:03:00"),
+            "2025-09-15T14:03:00+05:00"
+        );
+        assert_eq!(
+            to_rfc3339("2025-09-15T20:30:00Z"),
+            "2025-09-16T01:30:00+05:00"
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_values() {
+        assert_eq!(to_rfc3339(""), "");
+        assert_eq!(to_rfc3339("вчера"), "вчера");
+    }
+
+    #[test]
+    fn local_date_crosses_midnight() {
+        // 20:30 UTC — уже следующий день в Алматы
+        assert_eq!(local_date("2025-09-15 20:30:00"), parse_date("2025-09-16"));
+        assert_eq!(local_date("2025-09-15 18:59:59"), parse_date("2025-09-15"));
+        assert_eq!(local_date("2025-09-15"), None);
+    }
+
+    #[test]
+    fn date_bounds_in_store_time_zone() {
+        // Местный день 15.09 — с 19:00 UTC 14.09 до 19:00 UTC 15.09
+        assert_eq!(
+            query_bound_utc("2025-09-15", false).as_deref(),
+            Some("2025-09-14 19:00:00")
+        );
+        assert_eq!(
+            query_bound_utc("2025-09-15", true).as_deref(),
+            Some("2025-09-15 19:00:00")
+        );
+        assert_eq!(
+            query_bound_utc("2025-09-15T10:00:00+05:00", false).as_deref(),
+            Some("2025-09-15 05:00:00")
+        );
+        assert_eq!(query_bound_utc("15.09.2025", false), None);
+    }
+
+    #[test]
+    fn local_datetime_uses_store_time_zone() {
+        let dt = local_datetime(
+            parse_date("2025-09-15").unwrap(),
+            parse_time("08:00").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(format(&dt), "2025-09-15T08:00:00+05:00");
+        assert_eq!(
+            dt.with_timezone(&Utc).format(DB_FORMAT).to_string(),
+            "2025-09-15 03:00:00"
+        );
+    }
+
+    #[test]
+    fn configured_time_zone_with_dst() {
+        STORE_TZ.with(|cell| cell.set(chrono_tz::Europe::Berlin));
+        // 02:30 в ночь перевода часов не существует — значения нет
+        assert!(local_datetime(
+            parse_date("2025-03-30").unwrap(),
+            parse_time("02:30").unwrap()
+        )
+        .is_none());
+        assert_eq!(
+            to_rfc3339("2025-07-01 10:00:00"),
+            "2025-07-01T12:00:00+02:00"
+        );
+        STORE_TZ.with(|cell| cell.set(chrono_tz::Asia::Almaty));
+    }
+}
@@ -0,0 +1,133 @@
+// Минимальный CSV (RFC 4180): кавычки, "" внутри кавычек, переводы строк в значениях.
+// Excel с русской локалью сохраняет через ";", поэтому разделитель определяем по заголовку
+
+pub fn detect_delimiter(text: &str) -> char {
+    let header = text.lines().next().unwrap_or_default();
+    [';', ',', '\t']
+        .into_iter()
+        .max_by_key(|d| header.matches(*d).count())
+        .unwrap_or(',')
+}
+
+pub fn parse(text: &str) -> Vec<Vec<String>> {
+    let text = text.trim_start_matches('\u{feff}');
+    let delimiter = detect_delimiter(text);
+
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            c if c == delimiter => row.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    // Пустые строки в конце файла и между записями не нужны
+    rows.retain(|r| r.iter().any(|v| !v.trim().is_empty()));
+    rows
+}
+
+// Значение в кавычках, если в нём есть разделитель, кавычки или перевод строки
+pub fn escape(value: &str, delimiter: char) -> String {
+    if value.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn row<S: AsRef<str>>(values: &[S], delimiter: char) -> String {
+    let mut line = values
+        .iter()
+        .map(|v| escape(v.as_ref(), delimiter))
+        .collect::<Vec<_>>()
+        .join(&delimiter.to_string());
+    line.push_str("\r\n");
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_delimiter_from_header() {
+        assert_eq!(detect_delimiter("name;price\nМолоко;450"), ';');
+        assert_eq!(detect_delimiter("name,price"), ',');
+        assert_eq!(detect_delimiter("name\tprice"), '\t');
+    }
+
+    #[test]
+    fn parses_semicolon_file_with_bom() {
+        let rows = parse("\u{feff}name;price\r\nМолоко;450\r\nХлеб;120\r\n");
+        assert_eq!(
+            rows,
+            vec![
+                vec!["name", "price"],
+                vec!["Молоко", "450"],
+                vec!["Хлеб", "120"],
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_quoted_fields() {
+        let rows = parse("name,description\n\"Сыр \"\"Российский\"\"\",\"твёрдый,\nвесовой\"\n");
+        assert_eq!(
+            rows,
+            vec![
+                vec!["name", "description"],
+                vec!["Сыр \"Российский\"", "твёрдый,\nвесовой"],
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_blank_lines_and_keeps_empty_cells() {
+        let rows = parse("name;price;unit\n\nМолоко;;л\n;;\nХлеб;120");
+        assert_eq!(
+            rows,
+            vec![
+                vec!["name", "price", "unit"],
+                vec!["Молоко", "", "л"],
+                vec!["Хлеб", "120"],
+            ]
+        );
+    }
+
+    #[test]
+    fn row_round_trips() {
+        let line = row(&["Сыр; твёрдый", "say \"hi\"", "450"], ';');
+        assert_eq!(line, "\"Сыр; твёрдый\";\"say \"\"hi\"\"\";450\r\n");
+        assert_eq!(
+            parse(&format!("a;b;c\r\n{}", line))[1],
+            vec!["Сыр; твёрдый", "say \"hi\"", "450"]
+        );
+    }
+}
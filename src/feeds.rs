@@ -0,0 +1,332 @@
+// Товарные фиды для Яндекс Маркета (YML) и Google Merchant (RSS 2.0).
+// Собираются из D1 по расписанию (cron) и лежат в R2, публичные адреса отдают готовый файл
+use crate::clock;
+use crate::handlers::products;
+use crate::models::{Category, Product};
+use crate::storefront::{self, Storefront};
+use std::collections::{HashMap, HashSet};
+use worker::*;
+
+pub const CURRENCY: &str = "KZT";
+
+#[derive(Clone, Copy)]
+pub enum Feed {
+    Yandex,
+    Google,
+}
+
+impl Feed {
+    pub const ALL: [Feed; 2] = [Feed::Yandex, Feed::Google];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feed::Yandex => "yandex",
+            Feed::Google => "google",
+        }
+    }
+
+    // Ключ файла в R2
+    pub fn key(self) -> &'static str {
+        match self {
+            Feed::Yandex => "feeds/yandex.yml",
+            Feed::Google => "feeds/google.xml",
+        }
+    }
+
+    fn render(self, catalog: &Catalog, store: &Storefront) -> (String, usize) {
+        match self {
+            Feed::Yandex => yandex_yml(catalog, store),
+            Feed::Google => google_merchant(catalog, store),
+        }
+    }
+}
+
+pub fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Цена без лишних нулей: 1500 -> "1500", 99.9 -> "99.90"
+fn price(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+// Старая цена имеет смысл, только если она выше текущей
+fn old_price(product: &Product) -> Option<f64> {
+    product.old_price.filter(|old| *old > product.price)
+}
+
+fn in_stock(product: &Product) -> bool {
+    product.stock.unwrap_or(0.0) > 0.0
+}
+
+pub struct Catalog {
+    pub products: Vec<Product>,
+    pub categories: Vec<Category>,
+}
+
+impl Catalog {
+    // Скрытые товары в фиды не попадают, закончившиеся — с пометкой "нет в наличии".
+    // Товар с вариантами представлен вариантами (группа = id родителя)
+    pub async fn load(d1: &D1Database) -> Result<Self> {
+        let results = d1
+            .batch(vec![
+                d1.prepare("SELECT * FROM products WHERE is_hidden = 0 ORDER BY id"),
+                d1.prepare("SELECT * FROM categories ORDER BY id"),
+            ])
+            .await?;
+        let mut products = results[0].results::<Product>()?;
+        products::attach_barcodes(d1, &mut products).await?;
+        products::resolve_variants(d1, &mut products).await?;
+        let parents: HashSet<i32> = products.iter().filter_map(|p| p.parent_id).collect();
+        products.retain(|p| p.is_hidden == 0 && !parents.contains(&p.id));
+        Ok(Catalog {
+            products,
+            categories: results[1].results::<Category>()?,
+        })
+    }
+}
+
+// Путь категории для Google: "Молочные продукты > Сыры"
+fn category_path(by_id: &HashMap<i32, &Category>, category_id: i32) -> Option<String> {
+    let mut names = Vec::new();
+    let mut current = by_id.get(&category_id).copied();
+    // Ограничение глубины на случай зацикленных parent_id
+    while let Some(category) = current.filter(|_| names.len() < 10) {
+        names.push(category.name.clone());
+        current = category.parent_id.and_then(|id| by_id.get(&id).copied());
+    }
+    if names.is_empty() {
+        return None;
+    }
+    names.reverse();
+    Some(names.join(" > "))
+}
+
+fn yandex_yml(catalog: &Catalog, store: &Storefront) -> (String, usize) {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<yml_catalog date=\"{}\">\n<shop>\n",
+        clock::format(&clock::now_utc())
+    ));
+    xml.push_str(&format!(
+        "<name>{}</name>\n<company>{}</company>\n<url>{}</url>\n",
+        escape_xml(&store.name),
+        escape_xml(&store.company),
+        escape_xml(&store.url)
+    ));
+    xml.push_str(&format!(
+        "<currencies>\n<currency id=\"{}\" rate=\"1\"/>\n</currencies>\n",
+        CURRENCY
+    ));
+
+    xml.push_str("<categories>\n");
+    for category in &catalog.categories {
+        let parent = category
+            .parent_id
+            .map(|id| format!(" parentId=\"{}\"", id))
+            .unwrap_or_default();
+        xml.push_str(&format!(
+            "<category id=\"{}\"{}>{}</category>\n",
+            category.id,
+            parent,
+            escape_xml(&category.name)
+        ));
+    }
+    xml.push_str("</categories>\n<offers>\n");
+
+    for product in &catalog.products {
+        xml.push_str(&format!(
+            "<offer id=\"{}\"{} available=\"{}\">\n",
+            product.id,
+            product
+                .parent_id
+                .map(|id| format!(" group_id=\"{}\"", id))
+                .unwrap_or_default(),
+            in_stock(product)
+        ));
+        xml.push_str(&format!(
+            "<url>{}</url>\n",
+            escape_xml(&store.product_url(product.parent_id.unwrap_or(product.id)))
+        ));
+        xml.push_str(&format!("<price>{}</price>\n", price(product.price)));
+        if let Some(old) = old_price(product) {
+            xml.push_str(&format!("<oldprice>{}</oldprice>\n", price(old)));
+        }
+        xml.push_str(&format!("<currencyId>{}</currencyId>\n", CURRENCY));
+        if let Some(category_id) = product.category_id {
+            xml.push_str(&format!("<categoryId>{}</categoryId>\n", category_id));
+        }
+        for image in storefront::image_urls(product).iter().take(10) {
+            xml.push_str(&format!("<picture>{}</picture>\n", escape_xml(image)));
+        }
+        xml.push_str(&format!("<name>{}</name>\n", escape_xml(&product.name)));
+        for barcode in product.barcodes.iter().flatten() {
+            xml.push_str(&format!("<barcode>{}</barcode>\n", barcode));
+        }
+        if let Some(description) = product.description.as_deref().filter(|d| !d.is_empty()) {
+            xml.push_str(&format!(
+                "<description>{}</description>\n",
+                escape_xml(description)
+            ));
+        }
+        if let Some(stock) = product.stock {
+            xml.push_str(&format!("<count>{}</count>\n", stock.max(0.0).floor()));
+        }
+        xml.push_str("</offer>\n");
+    }
+
+    xml.push_str("</offers>\n</shop>\n</yml_catalog>\n");
+    (xml, catalog.products.len())
+}
+
+fn google_merchant(catalog: &Catalog, store: &Storefront) -> (String, usize) {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\" xmlns:g=\"http://base.google.com/ns/1.0\">\n<channel>\n",
+    );
+    xml.push_str(&format!(
+        "<title>{}</title>\n<link>{}</link>\n<description>{}</description>\n",
+        escape_xml(&store.name),
+        escape_xml(&store.url),
+        escape_xml(&store.company)
+    ));
+
+    let categories: HashMap<i32, &Category> =
+        catalog.categories.iter().map(|c| (c.id, c)).collect();
+    let mut items = 0;
+    for product in &catalog.products {
+        // Google не принимает товары без картинки
+        let images = storefront::image_urls(product);
+        let Some((image, additional)) = images.split_first() else {
+            continue;
+        };
+        items += 1;
+
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<g:id>{}</g:id>\n", product.id));
+        if let Some(parent_id) = product.parent_id {
+            xml.push_str(&format!(
+                "<g:item_group_id>{}</g:item_group_id>\n",
+                parent_id
+            ));
+        }
+        xml.push_str(&format!(
+            "<g:title>{}</g:title>\n",
+            escape_xml(&product.name)
+        ));
+        let description = product
+            .description
+            .as_deref()
+            .filter(|d| !d.is_empty())
+            .unwrap_or(&product.name);
+        xml.push_str(&format!(
+            "<g:description>{}</g:description>\n",
+            escape_xml(description)
+        ));
+        xml.push_str(&format!(
+            "<g:link>{}</g:link>\n",
+            escape_xml(&store.product_url(product.parent_id.unwrap_or(product.id)))
+        ));
+        xml.push_str(&format!(
+            "<g:image_link>{}</g:image_link>\n",
+            escape_xml(image)
+        ));
+        for url in additional.iter().take(10) {
+            xml.push_str(&format!(
+                "<g:additional_image_link>{}</g:additional_image_link>\n",
+                escape_xml(url)
+            ));
+        }
+        let availability = if in_stock(product) {
+            "in_stock"
+        } else {
+            "out_of_stock"
+        };
+        xml.push_str(&format!(
+            "<g:availability>{}</g:availability>\n",
+            availability
+        ));
+        // Со скидкой: price — старая цена, sale_price — текущая
+        match old_price(product) {
+            Some(old) => {
+                xml.push_str(&format!(
+                    "<g:price>{} {}</g:price>\n<g:sale_price>{} {}</g:sale_price>\n",
+                    price(old),
+                    CURRENCY,
+                    price(product.price),
+                    CURRENCY
+                ));
+            }
+            None => xml.push_str(&format!(
+                "<g:price>{} {}</g:price>\n",
+                price(product.price),
+                CURRENCY
+            )),
+        }
+        if let Some(path) = product
+            .category_id
+            .and_then(|id| category_path(&categories, id))
+        {
+            xml.push_str(&format!(
+                "<g:product_type>{}</g:product_type>\n",
+                escape_xml(&path)
+            ));
+        }
+        xml.push_str("<g:condition>new</g:condition>\n");
+        // Без штрихкода Google требует явно указать, что кода нет
+        match product.barcodes.iter().flatten().next() {
+            Some(gtin) => xml.push_str(&format!("<g:gtin>{}</g:gtin>\n", gtin)),
+            None => xml.push_str("<g:identifier_exists>no</g:identifier_exists>\n"),
+        }
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    (xml, items)
+}
+
+// Собирает фиды и сохраняет в R2. Возвращает число товаров в каждом фиде
+pub async fn regenerate(env: &Env) -> Result<Vec<(Feed, usize)>> {
+    let d1 = env.d1("akniet_db")?;
+    let bucket = env.bucket("akniet_bucket")?;
+    let catalog = Catalog::load(&d1).await?;
+    let store = Storefront::from_env(env);
+
+    let mut counts = Vec::new();
+    for feed in Feed::ALL {
+        let (xml, count) = feed.render(&catalog, &store);
+        store_feed(&bucket, feed, xml).await?;
+        counts.push((feed, count));
+    }
+    Ok(counts)
+}
+
+// Один фид: если в R2 его ещё нет (первый запрос до cron), собираем сразу
+pub async fn build(env: &Env, feed: Feed) -> Result<String> {
+    let d1 = env.d1("akniet_db")?;
+    let bucket = env.bucket("akniet_bucket")?;
+    let catalog = Catalog::load(&d1).await?;
+    let (xml, _) = feed.render(&catalog, &Storefront::from_env(env));
+    store_feed(&bucket, feed, xml.clone()).await?;
+    Ok(xml)
+}
+
+async fn store_feed(bucket: &Bucket, feed: Feed, xml: String) -> Result<()> {
+    bucket
+        .put(feed.key(), xml)
+        .http_metadata(HttpMetadata {
+            content_type: Some("application/xml; charset=utf-8".to_string()),
+            ..Default::default()
+        })
+        .execute()
+        .await?;
+    Ok(())
+}
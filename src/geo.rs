@@ -0,0 +1,192 @@
+// Проверка "точка в полигоне" для зон доставки.
+// Полигоны хранятся как GeoJSON: Polygon, MultiPolygon или Feature с такой геометрией.
+// Координаты в GeoJSON идут в порядке [долгота, широта]
+
+type Ring = Vec<(f64, f64)>;
+
+// Список полигонов; у каждого первое кольцо — внешняя граница, остальные — дырки
+fn polygons(geojson: &serde_json::Value) -> Vec<Vec<Ring>> {
+    let geometry = match geojson["type"].as_str() {
+        Some("Feature") => &geojson["geometry"],
+        _ => geojson,
+    };
+
+    let parse_ring = |ring: &serde_json::Value| -> Ring {
+        ring.as_array()
+            .map(|points| {
+                points
+                    .iter()
+                    .filter_map(|p| Some((p[0].as_f64()?, p[1].as_f64()?)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let parse_polygon = |polygon: &serde_json::Value| -> Vec<Ring> {
+        polygon
+            .as_array()
+            .map(|rings| rings.iter().map(parse_ring).collect())
+            .unwrap_or_default()
+    };
+
+    match geometry["type"].as_str() {
+        Some("Polygon") => vec![parse_polygon(&geometry["coordinates"])],
+        Some("MultiPolygon") => geometry["coordinates"]
+            .as_array()
+            .map(|list| list.iter().map(parse_polygon).collect())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+// Точка на ребре или в вершине кольца
+fn on_boundary(ring: &Ring, lng: f64, lat: f64) -> bool {
+    const EPSILON: f64 = 1e-9;
+    let n = ring.len();
+    (0..n).any(|i| {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[(i + 1) % n];
+        let cross = (xj - xi) * (lat - yi) - (yj - yi) * (lng - xi);
+        cross.abs() < EPSILON
+            && lng >= xi.min(xj) - EPSILON
+            && lng <= xi.max(xj) + EPSILON
+            && lat >= yi.min(yj) - EPSILON
+            && lat <= yi.max(yj) + EPSILON
+    })
+}
+
+// Метод луча: считаем пересечения горизонтального луча из точки с рёбрами кольца.
+// Граница кольца считается внутренней: адрес на границе зоны обслуживаем
+fn ring_contains(ring: &Ring, lng: f64, lat: f64) -> bool {
+    let mut inside = false;
+    let n = ring.len();
+    if n < 3 {
+        return false;
+    }
+    if on_boundary(ring, lng, lat) {
+        return true;
+    }
+
+    let mut j = n - 1;
+    for i in 0..n {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+        if (yi > lat) != (yj > lat) && lng < (xj - xi) * (lat - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+pub fn contains(geojson: &serde_json::Value, lat: f64, lng: f64) -> bool {
+    polygons(geojson)
+        .iter()
+        .any(|rings| match rings.split_first() {
+            Some((outer, holes)) => {
+                ring_contains(outer, lng, lat)
+                    && !holes
+                        .iter()
+                        .any(|h| ring_contains(h, lng, lat) && !on_boundary(h, lng, lat))
+            }
+            None => false,
+        })
+}
+
+// Годится ли строка как полигон зоны
+pub fn is_valid_polygon(geojson: &serde_json::Value) -> bool {
+    let list = polygons(geojson);
+    !list.is_empty()
+        && list
+            .iter()
+            .all(|rings| rings.first().is_some_and(|outer| outer.len() >= 3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(points: &[(f64, f64)]) -> serde_json::Value {
+        let ring: Vec<[f64; 2]> = points.iter().map(|&(lng, lat)| [lng, lat]).collect();
+        serde_json::json!({ "type": "Polygon", "coordinates": [ring] })
+    }
+
+    fn square() -> serde_json::Value {
+        polygon(&[
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 0.0),
+        ])
+    }
+
+    #[test]
+    fn point_inside() {
+        assert!(contains(&square(), 5.0, 5.0));
+    }
+
+    #[test]
+    fn point_outside() {
+        assert!(!contains(&square(), 5.0, 11.0));
+        assert!(!contains(&square(), -0.1, 5.0));
+    }
+
+    #[test]
+    fn point_on_vertex_and_edge() {
+        for (lat, lng) in [(0.0, 0.0), (10.0, 10.0), (0.0, 10.0), (10.0, 0.0)] {
+            assert!(contains(&square(), lat, lng), "vertex {} {}", lat, lng);
+        }
+        assert!(contains(&square(), 10.0, 5.0));
+        assert!(contains(&square(), 5.0, 0.0));
+    }
+
+    #[test]
+    fn concave_polygon() {
+        // Буква "П": выемка сверху посередине
+        let shape = polygon(&[
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (7.0, 10.0),
+            (7.0, 3.0),
+            (3.0, 3.0),
+            (3.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 0.0),
+        ]);
+        assert!(contains(&shape, 8.0, 1.5));
+        assert!(contains(&shape, 8.0, 8.5));
+        assert!(!contains(&shape, 8.0, 5.0));
+        assert!(!contains(&shape, 3.1, 5.0));
+    }
+
+    #[test]
+    fn polygon_with_hole() {
+        let shape = serde_json::json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [
+                    [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0], [0.0, 0.0]],
+                    [[4.0, 4.0], [6.0, 4.0], [6.0, 6.0], [4.0, 6.0], [4.0, 4.0]]
+                ]
+            }
+        });
+        assert!(contains(&shape, 2.0, 2.0));
+        assert!(!contains(&shape, 5.0, 5.0));
+        assert!(contains(&shape, 4.0, 5.0));
+    }
+
+    #[test]
+    fn multipolygon() {
+        let shape = serde_json::json!({
+            "type": "MultiPolygon",
+            "coordinates": [
+                [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0]]],
+                [[[20.0, 20.0], [30.0, 20.0], [30.0, 30.0], [20.0, 30.0], [20.0, 20.0]]]
+            ]
+        });
+        assert!(contains(&shape, 25.0, 25.0));
+        assert!(!contains(&shape, 10.0, 10.0));
+    }
+}
@@ -0,0 +1,572 @@
+use crate::handlers::products::{self, STOREFRONT_VISIBLE};
+use crate::models::{CategoryAttribute, Product, ProductAttribute};
+use serde::Deserialize;
+use std::collections::HashMap;
+use worker::*;
+
+// Характеристики товаров: набор задаётся для категории, значения — у товаров.
+// По ним фильтруется список товаров (?attr[brand]=Food Master&attr[fat]=3.2)
+// и считаются фасеты для витрины
+const KINDS: [&str; 4] = ["text", "number", "enum", "bool"];
+
+const MAX_TEXT_LENGTH: usize = 200;
+
+// Код — латиница, цифры и "_": он же ключ в адресе фильтра
+fn valid_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= 40
+        && code
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// "attr[brand]" -> "brand"
+pub fn filter_code(key: &str) -> Option<&str> {
+    key.strip_prefix("attr[")
+        .and_then(|rest| rest.strip_suffix(']'))
+        .filter(|code| valid_code(code))
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .replace(',', ".")
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+}
+
+// Фильтр по одной характеристике (" AND ..." и параметры). Несколько значений одного
+// кода — "или". Товар подходит, если значение есть у него или у его варианта.
+// Тип здесь неизвестен, поэтому значение сравнивается и текстом, и числом: "3.20" = "3.2", "1" = true
+pub fn attribute_filter(code: &str, values: &[&str]) -> (String, Vec<wasm_bindgen::JsValue>) {
+    let mut params: Vec<wasm_bindgen::JsValue> = vec![code.into()];
+    let conditions: Vec<&str> = values
+        .iter()
+        .map(|value| {
+            params.push(value.trim().into());
+            params.push(
+                parse_number(value)
+                    .map(|n| n.into())
+                    .unwrap_or(wasm_bindgen::JsValue::NULL),
+            );
+            "pav.value = ? COLLATE NOCASE OR pav.value_num = ?"
+        })
+        .collect();
+    let sql = format!(
+        " AND EXISTS (SELECT 1 FROM product_attribute_values pav
+           JOIN category_attributes ca ON ca.id = pav.attribute_id
+           JOIN products pv ON pv.id = pav.product_id
+           WHERE ca.code = ? AND (pv.id = products.id OR pv.parent_id = products.id)
+           AND ({}))",
+        conditions.join(" OR ")
+    );
+    (sql, params)
+}
+
+fn enum_options(attribute: &CategoryAttribute) -> Vec<String> {
+    attribute
+        .options
+        .as_deref()
+        .and_then(|o| serde_json::from_str(o).ok())
+        .unwrap_or_default()
+}
+
+// Значение из запроса -> (канонический текст, число). None — убрать значение
+fn parse_value(
+    attribute: &CategoryAttribute,
+    value: &serde_json::Value,
+) -> std::result::Result<Option<(String, Option<f64>)>, String> {
+    let text = match value {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::String(s) => s.trim().to_string(),
+        other => other.to_string(),
+    };
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let invalid = |expected: &str| {
+        Err(format!(
+            "«{}»: ожидается {}, получено «{}»",
+            attribute.name, expected, text
+        ))
+    };
+
+    match attribute.kind.as_str() {
+        "number" => match parse_number(&text) {
+            Some(n) => Ok(Some((n.to_string(), Some(n)))),
+            None => invalid("число"),
+        },
+        "bool" => match text.to_lowercase().as_str() {
+            "true" | "1" | "да" | "иә" => Ok(Some(("true".to_string(), Some(1.0)))),
+            "false" | "0" | "нет" | "жоқ" => Ok(Some(("false".to_string(), Some(0.0)))),
+            _ => invalid("да или нет"),
+        },
+        "enum" => {
+            let options = enum_options(attribute);
+            match options
+                .into_iter()
+                .find(|o| o.to_lowercase() == text.to_lowercase())
+            {
+                Some(option) => Ok(Some((option, None))),
+                None => invalid("одно из значений списка"),
+            }
+        }
+        _ if text.chars().count() > MAX_TEXT_LENGTH => Err(format!(
+            "«{}»: не длиннее {} символов",
+            attribute.name, MAX_TEXT_LENGTH
+        )),
+        _ => Ok(Some((text, None))),
+    }
+}
+
+struct AttributeInput {
+    code: String,
+    name: String,
+    name_kk: Option<String>,
+    kind: String,
+    options: Option<String>,
+    unit: Option<String>,
+    sort_order: i32,
+}
+
+fn attribute_from_json(body: &serde_json::Value) -> std::result::Result<AttributeInput, String> {
+    let text = |key: &str| {
+        body[key]
+            .as_str()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+
+    let code = text("code").unwrap_or_default().to_lowercase();
+    if !valid_code(&code) {
+        return Err("Код характеристики: латиница, цифры и _, например brand".to_string());
+    }
+    let Some(name) = text("name") else {
+        return Err("Укажите название характеристики".to_string());
+    };
+    let kind = text("type").unwrap_or_else(|| "text".to_string());
+    if !KINDS.contains(&kind.as_str()) {
+        return Err(format!("Тип характеристики: {}", KINDS.join(", ")));
+    }
+
+    let options = if kind == "enum" {
+        let mut options: Vec<String> = Vec::new();
+        for option in body["options"].as_array().into_iter().flatten() {
+            let option = option.as_str().unwrap_or("").trim().to_string();
+            let duplicate = options
+                .iter()
+                .any(|o| o.to_lowercase() == option.to_lowercase());
+            if !option.is_empty() && !duplicate {
+                options.push(option);
+            }
+        }
+        if options.is_empty() {
+            return Err("Для списка укажите варианты значений (options)".to_string());
+        }
+        Some(serde_json::to_string(&options).unwrap_or_default())
+    } else {
+        None
+    };
+
+    Ok(AttributeInput {
+        code,
+        name,
+        name_kk: text("name_kk"),
+        kind,
+        options,
+        unit: text("unit"),
+        sort_order: body["sort_order"].as_i64().unwrap_or(0) as i32,
+    })
+}
+
+async fn find_attribute(d1: &D1Database, id: &str) -> Result<Option<CategoryAttribute>> {
+    d1.prepare("SELECT * FROM category_attributes WHERE id = ?")
+        .bind(&[id.into()])?
+        .first::<CategoryAttribute>(None)
+        .await
+}
+
+// Значения характеристик товаров: product_id -> список в порядке, заданном в категории
+pub async fn load_attributes(
+    d1: &D1Database,
+    product_ids: &[i32],
+) -> Result<HashMap<i32, Vec<ProductAttribute>>> {
+    let mut attributes: HashMap<i32, Vec<ProductAttribute>> = HashMap::new();
+    if product_ids.is_empty() {
+        return Ok(attributes);
+    }
+    let rows = d1
+        .prepare(
+            "SELECT pav.product_id, ca.code, ca.name, ca.name_kk, ca.type, ca.unit, pav.value
+             FROM product_attribute_values pav
+             JOIN category_attributes ca ON ca.id = pav.attribute_id
+             WHERE pav.product_id IN (SELECT value FROM json_each(?))
+             ORDER BY ca.sort_order, ca.id",
+        )
+        .bind(&[serde_json::to_string(product_ids)?.into()])?
+        .all()
+        .await?
+        .results::<ProductAttribute>()?;
+    for row in rows {
+        attributes.entry(row.product_id).or_default().push(row);
+    }
+    Ok(attributes)
+}
+
+// Для batch после смены категории товаров: значения характеристик, которых нет
+// в (новой) категории товара или его вариантов, удаляются
+pub fn purge_foreign_values(d1: &D1Database, product_ids: &[i32]) -> Result<D1PreparedStatement> {
+    d1.prepare(
+        "DELETE FROM product_attribute_values
+         WHERE product_id IN (
+             SELECT id FROM products
+             WHERE id IN (SELECT value FROM json_each(?1)) OR parent_id IN (SELECT value FROM json_each(?1))
+         )
+         AND NOT EXISTS (
+             SELECT 1 FROM category_attributes ca
+             JOIN products p ON p.id = product_attribute_values.product_id
+             LEFT JOIN products parent ON parent.id = p.parent_id
+             WHERE ca.id = product_attribute_values.attribute_id
+               AND ca.category_id = COALESCE(p.category_id, parent.category_id)
+         )",
+    )
+    .bind(&[serde_json::to_string(product_ids)?.into()])
+}
+
+pub async fn attach_attributes(d1: &D1Database, products: &mut [Product]) -> Result<()> {
+    let ids: Vec<i32> = products.iter().map(|p| p.id).collect();
+    let mut attributes = load_attributes(d1, &ids).await?;
+    for product in products {
+        product.attributes = Some(attributes.remove(&product.id).unwrap_or_default());
+    }
+    Ok(())
+}
+
+// 1. Характеристики категории
+pub async fn list_attributes(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+
+    let attributes = d1
+        .prepare("SELECT * FROM category_attributes WHERE category_id = ? ORDER BY sort_order, id")
+        .bind(&[id.into()])?
+        .all()
+        .await?
+        .results::<CategoryAttribute>()?;
+
+    Response::from_json(&attributes)
+}
+
+// 2. Новая характеристика: { code, name, name_kk, type, options, unit, sort_order }
+pub async fn create_attribute(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let category_id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let attribute = match attribute_from_json(&body) {
+        Ok(attribute) => attribute,
+        Err(e) => return Response::error(e, 400),
+    };
+
+    let category = d1
+        .prepare("SELECT id FROM categories WHERE id = ?")
+        .bind(&[category_id.clone().into()])?
+        .first::<serde_json::Value>(None)
+        .await?;
+    if category.is_none() {
+        return Response::error("Категория не найдена", 404);
+    }
+
+    let result = d1
+        .prepare(
+            "INSERT INTO category_attributes (category_id, code, name, name_kk, type, options, unit, sort_order, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, datetime('now'), datetime('now'))
+             RETURNING *",
+        )
+        .bind(&[
+            category_id.into(),
+            attribute.code.into(),
+            attribute.name.into(),
+            attribute.name_kk.into(),
+            attribute.kind.into(),
+            attribute.options.into(),
+            attribute.unit.into(),
+            attribute.sort_order.into(),
+        ])?
+        .first::<CategoryAttribute>(None)
+        .await;
+
+    match result {
+        Ok(Some(created)) => Ok(Response::from_json(&created)?.with_status(201)),
+        Ok(None) => Response::error("Не удалось создать характеристику", 500),
+        Err(e) if e.to_string().contains("UNIQUE constraint failed") => {
+            Response::error("Характеристика с таким кодом уже есть в категории", 409)
+        }
+        Err(e) => Response::error(format!("D1 Error: {}", e), 500),
+    }
+}
+
+// 3. Изменение характеристики. Код и тип заполненной характеристики не меняются:
+// по коду работают фильтры, а значения записаны в формате типа
+pub async fn update_attribute(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let attribute = match attribute_from_json(&body) {
+        Ok(attribute) => attribute,
+        Err(e) => return Response::error(e, 400),
+    };
+    let Some(current) = find_attribute(&d1, &id).await? else {
+        return Response::error("Характеристика не найдена", 404);
+    };
+
+    if current.code != attribute.code || current.kind != attribute.kind {
+        let used = d1
+            .prepare("SELECT 1 FROM product_attribute_values WHERE attribute_id = ? LIMIT 1")
+            .bind(&[id.clone().into()])?
+            .first::<serde_json::Value>(None)
+            .await?;
+        if used.is_some() {
+            return Response::error(
+                "Характеристика уже заполнена у товаров — код и тип менять нельзя",
+                409,
+            );
+        }
+    }
+
+    let result = d1
+        .prepare(
+            "UPDATE category_attributes SET code=?, name=?, name_kk=?, type=?, options=?, unit=?, sort_order=?, updated_at=datetime('now')
+             WHERE id=? RETURNING *",
+        )
+        .bind(&[
+            attribute.code.into(),
+            attribute.name.into(),
+            attribute.name_kk.into(),
+            attribute.kind.into(),
+            attribute.options.into(),
+            attribute.unit.into(),
+            attribute.sort_order.into(),
+            id.into(),
+        ])?
+        .first::<CategoryAttribute>(None)
+        .await;
+
+    match result {
+        Ok(Some(updated)) => Response::from_json(&updated),
+        Ok(None) => Response::error("Характеристика не найдена", 404),
+        Err(e) if e.to_string().contains("UNIQUE constraint failed") => {
+            Response::error("Характеристика с таким кодом уже есть в категории", 409)
+        }
+        Err(e) => Response::error(format!("D1 Error: {}", e), 500),
+    }
+}
+
+// 4. Удаление характеристики вместе со значениями у товаров
+pub async fn delete_attribute(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+
+    d1.batch(vec![
+        d1.prepare("DELETE FROM product_attribute_values WHERE attribute_id = ?")
+            .bind(&[id.clone().into()])?,
+        d1.prepare("DELETE FROM category_attributes WHERE id = ?")
+            .bind(&[id.into()])?,
+    ])
+    .await?;
+
+    Response::ok("Deleted")
+}
+
+#[derive(Deserialize)]
+struct ProductCategory {
+    category_id: Option<i32>,
+}
+
+// 5. Значения характеристик товара: { "values": { "brand": "Food Master", "fat": 3.2 } }.
+// Переданные коды заменяются, null или "" — убрать значение, остальные не меняются.
+// Вариант берёт набор характеристик из категории родителя
+pub async fn set_product_attributes(
+    mut req: Request,
+    ctx: RouteContext<Context>,
+) -> Result<Response> {
+    let id: i32 = ctx.param("id").and_then(|s| s.parse().ok()).unwrap_or(0);
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let Some(values) = body["values"].as_object() else {
+        return Response::error("Передайте values: { код: значение }", 400);
+    };
+
+    let product = d1
+        .prepare(
+            "SELECT COALESCE(p.category_id, parent.category_id) AS category_id
+             FROM products p LEFT JOIN products parent ON parent.id = p.parent_id
+             WHERE p.id = ?",
+        )
+        .bind(&[id.into()])?
+        .first::<ProductCategory>(None)
+        .await?;
+    let Some(product) = product else {
+        return Response::error("Товар не найден", 404);
+    };
+    let Some(category_id) = product.category_id.filter(|id| *id > 0) else {
+        return Response::error("У товара не указана категория", 400);
+    };
+
+    let attributes: HashMap<String, CategoryAttribute> = d1
+        .prepare("SELECT * FROM category_attributes WHERE category_id = ?")
+        .bind(&[category_id.into()])?
+        .all()
+        .await?
+        .results::<CategoryAttribute>()?
+        .into_iter()
+        .map(|a| (a.code.clone(), a))
+        .collect();
+
+    let mut statements = Vec::new();
+    for (code, value) in values {
+        let Some(attribute) = attributes.get(code) else {
+            return Response::error(
+                format!("У категории товара нет характеристики «{}»", code),
+                400,
+            );
+        };
+        let statement = match parse_value(attribute, value) {
+            Ok(Some((text, number))) => d1
+                .prepare(
+                    "INSERT INTO product_attribute_values (product_id, attribute_id, value, value_num)
+                     VALUES (?, ?, ?, ?)
+                     ON CONFLICT (product_id, attribute_id) DO UPDATE SET value = excluded.value, value_num = excluded.value_num",
+                )
+                .bind(&[
+                    id.into(),
+                    attribute.id.into(),
+                    text.into(),
+                    number.into(),
+                ])?,
+            Ok(None) => d1
+                .prepare(
+                    "DELETE FROM product_attribute_values WHERE product_id = ? AND attribute_id = ?",
+                )
+                .bind(&[id.into(), attribute.id.into()])?,
+            Err(e) => return Response::error(e, 400),
+        };
+        statements.push(statement);
+    }
+    statements.push(
+        d1.prepare(
+            "UPDATE products SET version = version + 1, updated_at = datetime('now') WHERE id = ?",
+        )
+        .bind(&[id.into()])?,
+    );
+    d1.batch(statements).await?;
+
+    let attributes = load_attributes(&d1, &[id])
+        .await?
+        .remove(&id)
+        .unwrap_or_default();
+    Response::from_json(&attributes)
+}
+
+#[derive(Deserialize)]
+struct FacetValue {
+    value: String,
+    count: i32,
+}
+
+// 6. Фасеты: сколько товаров списка с каждым значением характеристики.
+// Фильтры те же, что у GET /api/products; фильтр самой характеристики при подсчёте
+// её значений не учитывается, чтобы можно было выбрать несколько значений
+pub async fn product_facets(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let query_pairs: Vec<(String, String)> = req.url()?.query_pairs().into_owned().collect();
+    let is_admin = query_pairs.iter().any(|(k, v)| k == "admin" && v == "true");
+    let category_id = query_pairs
+        .iter()
+        .find(|(k, _)| k == "categoryId" || k == "category_id")
+        .and_then(|(_, v)| v.parse::<i32>().ok());
+
+    let attributes = match category_id {
+        Some(category_id) => d1
+            .prepare(
+                "SELECT * FROM category_attributes WHERE category_id = ? ORDER BY sort_order, id",
+            )
+            .bind(&[category_id.into()])?,
+        None => d1.prepare("SELECT * FROM category_attributes ORDER BY sort_order, id"),
+    }
+    .all()
+    .await?
+    .results::<CategoryAttribute>()?;
+
+    // Один код в разных категориях — один фасет (название берём у первой)
+    let mut facets: Vec<CategoryAttribute> = Vec::new();
+    for attribute in attributes {
+        if !facets.iter().any(|f| f.code == attribute.code) {
+            facets.push(attribute);
+        }
+    }
+    if facets.is_empty() {
+        return Response::from_json(&serde_json::json!([]));
+    }
+
+    // Покупателю — как в списке: товары верхнего уровня, значения не скрытых вариантов.
+    // Админке — все товары верхнего уровня, вариант учитывается через родителя
+    let (base, variant_filter) = if is_admin {
+        ("parent_id IS NULL", "")
+    } else {
+        (
+            STOREFRONT_VISIBLE,
+            " AND (pv.id = p.id OR pv.is_hidden = 0)",
+        )
+    };
+
+    let mut statements = Vec::new();
+    for facet in &facets {
+        let other_filters: Vec<(String, String)> = query_pairs
+            .iter()
+            .filter(|(k, _)| filter_code(k) != Some(facet.code.as_str()))
+            .cloned()
+            .collect();
+        let (filter_sql, filter_params) = products::product_filters(&other_filters);
+        let mut params: Vec<wasm_bindgen::JsValue> = vec![facet.code.clone().into()];
+        params.extend(filter_params);
+        statements.push(
+            d1.prepare(format!(
+                "SELECT pav.value AS value, COUNT(DISTINCT p.id) AS count
+                 FROM products p
+                 JOIN products pv ON pv.id = p.id OR pv.parent_id = p.id
+                 JOIN product_attribute_values pav ON pav.product_id = pv.id
+                 JOIN category_attributes ca ON ca.id = pav.attribute_id
+                 WHERE ca.code = ?{}
+                 AND p.id IN (SELECT id FROM products WHERE {}{})
+                 GROUP BY pav.value ORDER BY count DESC, pav.value",
+                variant_filter, base, filter_sql
+            ))
+            .bind(&params)?,
+        );
+    }
+    let results = d1.batch(statements).await?;
+
+    let mut report = Vec::new();
+    for (facet, result) in facets.iter().zip(results) {
+        let values = result.results::<FacetValue>()?;
+        if values.is_empty() {
+            continue;
+        }
+        report.push(serde_json::json!({
+            "code": facet.code,
+            "name": facet.name,
+            "name_kk": facet.name_kk,
+            "type": facet.kind,
+            "unit": facet.unit,
+            "values": values
+                .iter()
+                .map(|v| serde_json::json!({ "value": v.value, "count": v.count }))
+                .collect::<Vec<_>>(),
+        }));
+    }
+    Response::from_json(&report)
+}
@@ -1,8 +1,12 @@
+use crate::handlers::products;
+use crate::jobs::{self, Job};
 use crate::models::Category;
+use crate::patch::{self, FieldKind};
+use crate::versioning;
 use uuid::Uuid;
 use worker::*;
 
-pub async fn list_categories(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn list_categories(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     // Подключение к базе данных
     let d1 = ctx.env.d1("akniet_db")?;
 
@@ -18,7 +22,7 @@ pub async fn list_categories(_req: Request, ctx: RouteContext<()>) -> Result<Res
 }
 
 //Добавление категории
-pub async fn create_category(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn create_category(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let form = req.form_data().await?;
     let bucket = ctx.env.bucket("akniet_bucket")?;
     let d1 = ctx.env.d1("akniet_db")?;
@@ -67,21 +71,23 @@ pub async fn create_category(mut req: Request, ctx: RouteContext<()>) -> Result<
         .and_then(|s| s.parse::<i32>().ok());
 
     let mut image_url = String::new();
+    let mut uploads = Vec::new();
 
     // Обработка картинки категории
-    if let Some(entry) = form.get("imageFile") {
-        if let FormEntry::File(file) = entry {
-            let file_name = format!("cat-{}.jpg", Uuid::new_v4());
-            let bytes = file.bytes().await?;
-            bucket.put(&file_name, bytes).execute().await?;
-            image_url = format!("https://img.tabys-go.ru/{}", file_name);
-        }
+    if let Some(FormEntry::File(file)) = form.get("imageFile") {
+        let file_name = format!("cat-{}.jpg", Uuid::new_v4());
+        let bytes = file.bytes().await?;
+        bucket.put(&file_name, bytes).execute().await?;
+        uploads.push(Job::ImageUploaded {
+            key: file_name.clone(),
+        });
+        image_url = format!("https://img.tabys-go.ru/{}", file_name);
     }
 
     // Сохраняем в базу
     let result = d1
         .prepare(
-            "INSERT INTO categories (name, name_kk, slug, parent_id, image) VALUES (?, ?, ?, ?, ?)",
+            "INSERT INTO categories (name, name_kk, slug, parent_id, image, created_at, updated_at) VALUES (?, ?, ?, ?, ?, datetime('now'), datetime('now'))",
         )
         .bind(&[
             name.trim().into(),
@@ -97,7 +103,10 @@ pub async fn create_category(mut req: Request, ctx: RouteContext<()>) -> Result<
         .await;
 
     match result {
-        Ok(_) => Response::ok("Category created"),
+        Ok(_) => {
+            jobs::enqueue(&ctx, uploads);
+            Response::ok("Category created")
+        }
         Err(e) => {
             if e.to_string().contains("UNIQUE constraint failed") {
                 Response::error("Slug уже существует", 400)
@@ -110,7 +119,7 @@ pub async fn create_category(mut req: Request, ctx: RouteContext<()>) -> Result<
 
 //Удаление категории
 
-pub async fn delete_category(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn delete_category(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
     let bucket = ctx.env.bucket("akniet_bucket")?;
 
@@ -126,15 +135,22 @@ pub async fn delete_category(mut req: Request, ctx: RouteContext<()>) -> Result<
         return Response::error("ID категории не передан", 400);
     }
 
-    // 1. Удаляем из базы
-    d1.prepare("DELETE FROM categories WHERE id = ?")
-        .bind(&[id.into()])?
-        .run()
-        .await?;
+    // 1. Удаляем из базы вместе с характеристиками категории и их значениями у товаров
+    d1.batch(vec![
+        d1.prepare(
+            "DELETE FROM product_attribute_values WHERE attribute_id IN (SELECT id FROM category_attributes WHERE category_id = ?)",
+        )
+        .bind(&[id.into()])?,
+        d1.prepare("DELETE FROM category_attributes WHERE category_id = ?")
+            .bind(&[id.into()])?,
+        d1.prepare("DELETE FROM categories WHERE id = ?")
+            .bind(&[id.into()])?,
+    ])
+    .await?;
 
     // 2. Удаляем картинку из базы картинок, если она есть
     if !image_url.is_empty() && image_url.contains('/') {
-        if let Some(file_name) = image_url.split('/').last() {
+        if let Some(file_name) = image_url.split('/').next_back() {
             let _ = bucket.delete(file_name).await;
         }
     }
@@ -143,7 +159,7 @@ pub async fn delete_category(mut req: Request, ctx: RouteContext<()>) -> Result<
 }
 
 //Получить категорию
-pub async fn get_category(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn get_category(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
     let d1 = ctx.env.d1("akniet_db")?;
 
@@ -152,19 +168,41 @@ pub async fn get_category(_req: Request, ctx: RouteContext<()>) -> Result<Respon
         .bind(&[id.into()])?;
 
     match statement.first::<Category>(None).await {
-        Ok(Some(cat)) => Response::from_json(&cat),
+        Ok(Some(cat)) => versioning::with_etag(Response::from_json(&cat)?, cat.version),
         Ok(None) => Response::error("Категория не найдена", 404),
         Err(e) => Response::error(format!("D1 Error: {}", e), 500),
     }
 }
 
+async fn find_category(d1: &D1Database, id: &str) -> Result<Option<Category>> {
+    d1.prepare("SELECT * FROM categories WHERE id = ?")
+        .bind(&[id.into()])?
+        .first::<Category>(None)
+        .await
+}
+
 // 2. Обновить категорию
-pub async fn update_category(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn update_category(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
     let form = req.form_data().await?;
     let d1 = ctx.env.d1("akniet_db")?;
     let bucket = ctx.env.bucket("akniet_bucket")?;
 
+    // Версия, с которой админ открыл категорию: If-Match или поле version
+    let version_field = match form.get("version") {
+        Some(FormEntry::Field(s)) => Some(serde_json::Value::String(s)),
+        _ => None,
+    };
+    let Some(version) = versioning::expected_version(&req, version_field.as_ref()) else {
+        return versioning::version_required();
+    };
+    let Some(current) = find_category(&d1, &id).await? else {
+        return Response::error("Категория не найдена", 404);
+    };
+    if current.version != version {
+        return versioning::conflict(&current, current.version);
+    }
+
     // Извлекаем поля с защитой от пустых значений
     let name = form
         .get("name")
@@ -228,23 +266,23 @@ pub async fn update_category(mut req: Request, ctx: RouteContext<()>) -> Result<
         })
         .unwrap_or_default();
 
-    // Если загрузили новый файл
-    if let Some(entry) = form.get("imageFile") {
-        if let FormEntry::File(file) = entry {
-            if file.size() > 0 {
-                let file_name = format!("cat-{}.jpg", uuid::Uuid::new_v4());
-                bucket
-                    .put(&file_name, file.bytes().await?)
-                    .execute()
-                    .await?;
-                image_url = format!("https://img.tabys-go.ru/{}", file_name);
-            }
+    // Если загрузили новый файл. Сохранить категорию не удалось — файл удаляется
+    let mut uploaded = Vec::new();
+    if let Some(FormEntry::File(file)) = form.get("imageFile") {
+        if file.size() > 0 {
+            let file_name = format!("cat-{}.jpg", Uuid::new_v4());
+            bucket
+                .put(&file_name, file.bytes().await?)
+                .execute()
+                .await?;
+            image_url = format!("https://img.tabys-go.ru/{}", file_name);
+            uploaded.push(file_name);
         }
     }
 
     // UPDATE в базе
     let result = d1
-        .prepare("UPDATE categories SET name=?, name_kk=?, slug=?, parent_id=?, image=? WHERE id=?")
+        .prepare("UPDATE categories SET name=?, name_kk=?, slug=?, parent_id=?, image=?, version=version+1, updated_at=datetime('now') WHERE id=? AND version=?")
         .bind(&[
             name.trim().into(),
             name_kk.trim().into(),
@@ -253,13 +291,92 @@ pub async fn update_category(mut req: Request, ctx: RouteContext<()>) -> Result<
                 .map(|id| id.into())
                 .unwrap_or(wasm_bindgen::JsValue::NULL), // Явный NULL
             image_url.into(),
-            id.into(),
+            id.clone().into(),
+            version.into(),
         ])?
         .run()
         .await;
 
+    let updated = match result {
+        Ok(result) => result.meta()?.and_then(|m| m.changes).unwrap_or(0) > 0,
+        Err(e) => {
+            products::delete_uploads(&bucket, &uploaded).await;
+            return Response::error(format!("D1 Update Error: {}", e), 500);
+        }
+    };
+    // Кто-то успел сохранить категорию между проверкой и UPDATE
+    if !updated {
+        products::delete_uploads(&bucket, &uploaded).await;
+        return match find_category(&d1, &id).await? {
+            Some(current) => versioning::conflict(&current, current.version),
+            None => Response::error("Категория не найдена", 404),
+        };
+    }
+
+    jobs::enqueue(
+        &ctx,
+        uploaded
+            .into_iter()
+            .map(|key| Job::ImageUploaded { key })
+            .collect(),
+    );
+    versioning::with_etag(Response::ok("Updated")?, version + 1)
+}
+
+// PATCH категории (JSON): { "name": "...", "parent_id": null, "version": 2 }
+pub async fn patch_category(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let body: serde_json::Value = req.json().await?;
+
+    let Some(version) = versioning::expected_version(&req, body.get("version")) else {
+        return versioning::version_required();
+    };
+
+    let patch = match patch::build(
+        &body,
+        &[
+            ("name", FieldKind::Text),
+            ("name_kk", FieldKind::Text),
+            ("slug", FieldKind::Slug),
+            ("parent_id", FieldKind::NullableId),
+            ("image", FieldKind::NullableText),
+        ],
+    ) {
+        Ok(patch) => patch,
+        Err(e) => return Response::error(e, 400),
+    };
+    if patch.is_empty() {
+        return Response::error("Нет полей для изменения", 400);
+    }
+    let parent_id = patch.value("parent_id").and_then(|v| v.as_f64());
+    if parent_id.is_some_and(|parent| parent.to_string() == id) {
+        return Response::error("Категория не может быть родителем самой себя", 400);
+    }
+
+    let mut params = patch.params.clone();
+    params.push(id.clone().into());
+    params.push(version.into());
+    let result = d1
+        .prepare(format!(
+            "UPDATE categories SET {}, version = version + 1, updated_at = datetime('now') WHERE id = ? AND version = ? RETURNING *",
+            patch.set_clause()
+        ))
+        .bind(&params)?
+        .first::<Category>(None)
+        .await;
+
     match result {
-        Ok(_) => Response::ok("Updated"),
+        Ok(Some(category)) => {
+            versioning::with_etag(Response::from_json(&category)?, category.version)
+        }
+        Ok(None) => match find_category(&d1, &id).await? {
+            Some(current) => versioning::conflict(&current, current.version),
+            None => Response::error("Категория не найдена", 404),
+        },
+        Err(e) if e.to_string().contains("UNIQUE constraint failed") => {
+            Response::error("Slug уже существует", 400)
+        }
         Err(e) => Response::error(format!("D1 Update Error: {}", e), 500),
     }
 }
@@ -0,0 +1,520 @@
+use crate::clock;
+use crate::geo;
+use crate::models::{DeliverySlot, DeliveryZone};
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::Deserialize;
+use worker::*;
+
+// На сколько дней вперёд можно выбрать слот
+const MAX_SLOT_DAYS: i64 = 14;
+
+#[derive(Deserialize)]
+struct SlotBooking {
+    slot_id: i32,
+    slot_date: String,
+    booked: i32,
+}
+
+// Координаты и район из запроса: { lat, lng } или { latitude, longitude }
+pub struct DeliveryPoint {
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub district: String,
+    pub address: String,
+}
+
+impl DeliveryPoint {
+    pub fn from_json(value: &serde_json::Value) -> Self {
+        let coord = |a: &str, b: &str| {
+            value[a]
+                .as_f64()
+                .or_else(|| value[b].as_f64())
+                .or_else(|| value[a].as_str().and_then(|s| s.parse().ok()))
+        };
+        DeliveryPoint {
+            lat: coord("lat", "latitude"),
+            lng: coord("lng", "longitude"),
+            district: value["district"].as_str().unwrap_or("").trim().to_string(),
+            address: value["address"].as_str().unwrap_or("").trim().to_string(),
+        }
+    }
+}
+
+pub async fn active_zones(d1: &D1Database) -> Result<Vec<DeliveryZone>> {
+    d1.prepare("SELECT * FROM delivery_zones WHERE is_active = 1 ORDER BY id")
+        .all()
+        .await?
+        .results::<DeliveryZone>()
+}
+
+// Подбор зоны: сначала по координатам (полигон), затем по району или улице в адресе
+pub fn match_zone<'a>(
+    zones: &'a [DeliveryZone],
+    point: &DeliveryPoint,
+) -> Option<&'a DeliveryZone> {
+    if let (Some(lat), Some(lng)) = (point.lat, point.lng) {
+        let by_polygon = zones.iter().find(|zone| {
+            zone.polygon
+                .as_deref()
+                .and_then(|p| serde_json::from_str::<serde_json::Value>(p).ok())
+                .is_some_and(|p| geo::contains(&p, lat, lng))
+        });
+        if by_polygon.is_some() {
+            return by_polygon;
+        }
+    }
+
+    let district = point.district.to_lowercase();
+    let address = point.address.to_lowercase();
+
+    zones.iter().find(|zone| {
+        let names: Vec<String> = zone
+            .districts
+            .as_deref()
+            .and_then(|d| serde_json::from_str(d).ok())
+            .unwrap_or_default();
+        names
+            .iter()
+            .map(|n| n.trim().to_lowercase())
+            .any(|name| !name.is_empty() && (district == name || address.contains(&name)))
+    })
+}
+
+// Стоимость доставки с учётом порога бесплатной доставки
+pub fn delivery_fee(zone: &DeliveryZone, subtotal: f64) -> f64 {
+    match zone.free_delivery_from {
+        Some(threshold) if subtotal >= threshold => 0.0,
+        _ => zone.delivery_fee,
+    }
+}
+
+// Доставка в зону на сумму товаров: стоимость или ошибка, если сумма меньше минимальной
+pub fn quote(zone: &DeliveryZone, subtotal: f64) -> std::result::Result<f64, String> {
+    if subtotal < zone.min_order {
+        return Err(format!(
+            "Минимальная сумма заказа для зоны «{}» — {} ₸",
+            zone.name, zone.min_order
+        ));
+    }
+    Ok(delivery_fee(zone, subtotal))
+}
+
+// Зона заказа, в том числе уже отключённая
+pub async fn find_zone(d1: &D1Database, id: i32) -> Result<Option<DeliveryZone>> {
+    d1.prepare("SELECT * FROM delivery_zones WHERE id = ?")
+        .bind(&[id.into()])?
+        .first::<DeliveryZone>(None)
+        .await
+}
+
+fn zone_from_json(body: &serde_json::Value) -> std::result::Result<DeliveryZone, &'static str> {
+    let name = body["name"].as_str().unwrap_or("").trim().to_string();
+    if name.is_empty() {
+        return Err("Укажите название зоны");
+    }
+
+    // Полигон принимаем и объектом, и строкой
+    let polygon = match &body["polygon"] {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) if s.trim().is_empty() => None,
+        serde_json::Value::String(s) => {
+            Some(serde_json::from_str::<serde_json::Value>(s).map_err(|_| "Неверный GeoJSON")?)
+        }
+        value => Some(value.clone()),
+    };
+    if let Some(polygon) = &polygon {
+        if !geo::is_valid_polygon(polygon) {
+            return Err("Полигон должен быть GeoJSON Polygon или MultiPolygon");
+        }
+    }
+
+    let districts: Vec<String> = body["districts"]
+        .as_array()
+        .map(|list| {
+            list.iter()
+                .filter_map(|d| d.as_str())
+                .map(|d| d.trim().to_string())
+                .filter(|d| !d.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if polygon.is_none() && districts.is_empty() {
+        return Err("Укажите полигон или список районов");
+    }
+
+    Ok(DeliveryZone {
+        id: None,
+        name,
+        name_kk: body["name_kk"].as_str().map(|s| s.trim().to_string()),
+        polygon: polygon.map(|p| p.to_string()),
+        districts: Some(serde_json::to_string(&districts).unwrap_or_default()),
+        delivery_fee: body["delivery_fee"].as_f64().unwrap_or(0.0),
+        free_delivery_from: body["free_delivery_from"].as_f64(),
+        min_order: body["min_order"].as_f64().unwrap_or(0.0),
+        is_active: Some(if body["is_active"].as_bool() == Some(false) {
+            0
+        } else {
+            1
+        }),
+    })
+}
+
+// 1. Список зон (для витрины — только активные, admin=true — все)
+pub async fn list_zones(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let url = req.url()?;
+    let is_admin = url.query_pairs().any(|(k, v)| k == "admin" && v == "true");
+
+    let zones = if is_admin {
+        d1.prepare("SELECT * FROM delivery_zones ORDER BY id")
+            .all()
+            .await?
+            .results::<DeliveryZone>()?
+    } else {
+        active_zones(&d1).await?
+    };
+
+    Response::from_json(&zones)
+}
+
+// 2. Создание зоны
+pub async fn create_zone(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let zone = match zone_from_json(&body) {
+        Ok(zone) => zone,
+        Err(e) => return Response::error(e, 400),
+    };
+
+    d1.prepare(
+        "INSERT INTO delivery_zones (name, name_kk, polygon, districts, delivery_fee, free_delivery_from, min_order, is_active)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&[
+        zone.name.into(),
+        zone.name_kk.into(),
+        zone.polygon.into(),
+        zone.districts.into(),
+        zone.delivery_fee.into(),
+        zone.free_delivery_from.into(),
+        zone.min_order.into(),
+        zone.is_active.into(),
+    ])?
+    .run()
+    .await?;
+
+    Response::ok("Created")
+}
+
+// 3. Изменение зоны
+pub async fn update_zone(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let zone = match zone_from_json(&body) {
+        Ok(zone) => zone,
+        Err(e) => return Response::error(e, 400),
+    };
+
+    d1.prepare(
+        "UPDATE delivery_zones SET name=?, name_kk=?, polygon=?, districts=?, delivery_fee=?, free_delivery_from=?, min_order=?, is_active=? WHERE id=?",
+    )
+    .bind(&[
+        zone.name.into(),
+        zone.name_kk.into(),
+        zone.polygon.into(),
+        zone.districts.into(),
+        zone.delivery_fee.into(),
+        zone.free_delivery_from.into(),
+        zone.min_order.into(),
+        zone.is_active.into(),
+        id.into(),
+    ])?
+    .run()
+    .await?;
+
+    Response::ok("Updated")
+}
+
+// 4. Удаление зоны
+pub async fn delete_zone(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+
+    d1.prepare("DELETE FROM delivery_zones WHERE id = ?")
+        .bind(&[id.into()])?
+        .run()
+        .await?;
+
+    Response::ok("Deleted")
+}
+
+// 5. Расчёт доставки для корзины: { lat, lng, district, address, subtotal }
+pub async fn quote_delivery(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let zones = active_zones(&d1).await?;
+    if zones.is_empty() {
+        return Response::from_json(&serde_json::json!({ "zone": null, "delivery_fee": 0 }));
+    }
+
+    let point = DeliveryPoint::from_json(&body);
+    let Some(zone) = match_zone(&zones, &point) else {
+        return Response::error("Адрес вне зоны доставки", 422);
+    };
+
+    let subtotal = body["subtotal"].as_f64().unwrap_or(0.0);
+
+    Response::from_json(&serde_json::json!({
+        "zone": zone,
+        "delivery_fee": delivery_fee(zone, subtotal),
+        "min_order": zone.min_order,
+        "free_delivery_from": zone.free_delivery_from,
+    }))
+}
+
+fn slot_from_json(body: &serde_json::Value) -> std::result::Result<DeliverySlot, &'static str> {
+    let weekday = body["weekday"].as_i64().unwrap_or(0) as i32;
+    if !(1..=7).contains(&weekday) {
+        return Err("День недели должен быть от 1 (пн) до 7 (вс)");
+    }
+
+    let start_time = body["start_time"].as_str().unwrap_or("");
+    let end_time = body["end_time"].as_str().unwrap_or("");
+    let (Some(start), Some(end)) = (clock::parse_time(start_time), clock::parse_time(end_time))
+    else {
+        return Err("Время слота должно быть в формате ЧЧ:ММ");
+    };
+    if start >= end {
+        return Err("Начало слота должно быть раньше конца");
+    }
+
+    let capacity = body["capacity"].as_i64().unwrap_or(0) as i32;
+    if capacity < 0 {
+        return Err("Вместимость слота не может быть отрицательной");
+    }
+
+    Ok(DeliverySlot {
+        id: None,
+        weekday,
+        start_time: start.format("%H:%M").to_string(),
+        end_time: end.format("%H:%M").to_string(),
+        capacity,
+        cutoff_minutes: body["cutoff_minutes"].as_i64().unwrap_or(60).max(0) as i32,
+        is_active: Some(if body["is_active"].as_bool() == Some(false) {
+            0
+        } else {
+            1
+        }),
+    })
+}
+
+// Можно ли ещё записаться на слот в эту дату (без учёта заполненности)
+fn slot_open(slot: &DeliverySlot, date: NaiveDate) -> bool {
+    if slot.is_active != Some(1) || date.weekday().number_from_monday() as i32 != slot.weekday {
+        return false;
+    }
+
+    let today = clock::today();
+    if date < today || date > today + Duration::days(MAX_SLOT_DAYS) {
+        return false;
+    }
+
+    let Some(start) =
+        clock::parse_time(&slot.start_time).and_then(|time| clock::local_datetime(date, time))
+    else {
+        return false;
+    };
+    clock::now_local() + Duration::minutes(slot.cutoff_minutes as i64) < start
+}
+
+// Проверка выбранного при оформлении слота
+pub async fn find_open_slot(
+    d1: &D1Database,
+    slot_id: i32,
+    date: &str,
+) -> Result<std::result::Result<NaiveDate, &'static str>> {
+    let Some(date) = clock::parse_date(date) else {
+        return Ok(Err("Дата доставки должна быть в формате ГГГГ-ММ-ДД"));
+    };
+
+    let slot = d1
+        .prepare("SELECT * FROM delivery_slots WHERE id = ?")
+        .bind(&[slot_id.into()])?
+        .first::<DeliverySlot>(None)
+        .await?;
+
+    match slot {
+        Some(slot) if slot_open(&slot, date) => Ok(Ok(date)),
+        Some(_) => Ok(Err("Запись на этот слот доставки закрыта")),
+        None => Ok(Err("Слот доставки не найден")),
+    }
+}
+
+// Бронь места в слоте. При переполнении срабатывает CHECK (booked <= capacity)
+// и откатывается весь batch, в который входит запрос
+pub fn reserve_slot(d1: &D1Database, slot_id: i32, date: NaiveDate) -> Result<D1PreparedStatement> {
+    d1.prepare(
+        "INSERT INTO delivery_slot_bookings (slot_id, slot_date, booked, capacity)
+         SELECT id, ?, 1, capacity FROM delivery_slots WHERE id = ?
+         ON CONFLICT(slot_id, slot_date) DO UPDATE SET booked = booked + 1",
+    )
+    .bind(&[date.to_string().into(), slot_id.into()])
+}
+
+// Переполнение слота: сработал CHECK (booked <= capacity) из reserve_slot
+pub fn is_slot_full(e: &Error) -> bool {
+    e.to_string()
+        .contains("CHECK constraint failed: booked <= capacity")
+}
+
+// 6. Свободные слоты на ближайшие дни: GET /api/delivery-slots?days=7
+pub async fn available_slots(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let url = req.url()?;
+    let days = url
+        .query_pairs()
+        .find(|(k, _)| k == "days")
+        .and_then(|(_, v)| v.parse::<i64>().ok())
+        .unwrap_or(7)
+        .clamp(1, MAX_SLOT_DAYS);
+
+    let today = clock::today();
+    let last_day = today + Duration::days(days - 1);
+
+    let results = d1
+        .batch(vec![
+            d1.prepare(
+                "SELECT * FROM delivery_slots WHERE is_active = 1 ORDER BY weekday, start_time",
+            ),
+            d1.prepare(
+                "SELECT slot_id, slot_date, booked FROM delivery_slot_bookings WHERE slot_date BETWEEN ? AND ?",
+            )
+            .bind(&[today.to_string().into(), last_day.to_string().into()])?,
+        ])
+        .await?;
+
+    let slots = results[0].results::<DeliverySlot>()?;
+    let bookings = results[1].results::<SlotBooking>()?;
+
+    let mut result = Vec::new();
+    for offset in 0..days {
+        let date = today + Duration::days(offset);
+        let date_str = date.to_string();
+
+        let day_slots: Vec<serde_json::Value> = slots
+            .iter()
+            .filter(|slot| slot_open(slot, date))
+            .map(|slot| {
+                let booked = bookings
+                    .iter()
+                    .find(|b| Some(b.slot_id) == slot.id && b.slot_date == date_str)
+                    .map(|b| b.booked)
+                    .unwrap_or(0);
+                let available = (slot.capacity - booked).max(0);
+                serde_json::json!({
+                    "id": slot.id,
+                    "start_time": slot.start_time,
+                    "end_time": slot.end_time,
+                    "capacity": slot.capacity,
+                    "available": available,
+                })
+            })
+            .collect();
+
+        result.push(serde_json::json!({
+            "date": date_str,
+            "weekday": date.weekday().number_from_monday(),
+            "slots": day_slots,
+        }));
+    }
+
+    Response::from_json(&result)
+}
+
+// 7. Расписание слотов для админки
+pub async fn list_slots(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let slots = d1
+        .prepare("SELECT * FROM delivery_slots ORDER BY weekday, start_time")
+        .all()
+        .await?
+        .results::<DeliverySlot>()?;
+    Response::from_json(&slots)
+}
+
+// 8. Создание слота
+pub async fn create_slot(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let slot = match slot_from_json(&body) {
+        Ok(slot) => slot,
+        Err(e) => return Response::error(e, 400),
+    };
+
+    d1.prepare(
+        "INSERT INTO delivery_slots (weekday, start_time, end_time, capacity, cutoff_minutes, is_active) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&[
+        slot.weekday.into(),
+        slot.start_time.into(),
+        slot.end_time.into(),
+        slot.capacity.into(),
+        slot.cutoff_minutes.into(),
+        slot.is_active.into(),
+    ])?
+    .run()
+    .await?;
+
+    Response::ok("Created")
+}
+
+// 9. Изменение слота. Вместимость уже забронированных дат не опускаем ниже числа заказов
+pub async fn update_slot(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let slot = match slot_from_json(&body) {
+        Ok(slot) => slot,
+        Err(e) => return Response::error(e, 400),
+    };
+
+    d1.batch(vec![
+        d1.prepare(
+            "UPDATE delivery_slots SET weekday=?, start_time=?, end_time=?, capacity=?, cutoff_minutes=?, is_active=? WHERE id=?",
+        )
+        .bind(&[
+            slot.weekday.into(),
+            slot.start_time.into(),
+            slot.end_time.into(),
+            slot.capacity.into(),
+            slot.cutoff_minutes.into(),
+            slot.is_active.into(),
+            id.clone().into(),
+        ])?,
+        d1.prepare("UPDATE delivery_slot_bookings SET capacity = MAX(booked, ?) WHERE slot_id = ?")
+            .bind(&[slot.capacity.into(), id.into()])?,
+    ])
+    .await?;
+
+    Response::ok("Updated")
+}
+
+// 10. Удаление слота
+pub async fn delete_slot(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+
+    d1.prepare("DELETE FROM delivery_slots WHERE id = ?")
+        .bind(&[id.into()])?
+        .run()
+        .await?;
+
+    Response::ok("Deleted")
+}
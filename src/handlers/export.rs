@@ -0,0 +1,355 @@
+use crate::clock;
+use crate::csv;
+use crate::handlers::{orders, products};
+use crate::models::{Category, Order, Product};
+use futures_util::stream;
+use std::collections::HashMap;
+use worker::*;
+
+// Выгрузка каталога и заказов в CSV/JSON для бухгалтерии и резервных копий.
+// Записи читаются из D1 страницами по id и сразу отдаются клиенту,
+// поэтому размер выгрузки не ограничен памятью воркера
+const PAGE_SIZE: i32 = 500;
+
+// Excel с русской локалью ждёт ";" — с ним же работает импорт
+const DELIMITER: char = ';';
+
+const PRODUCT_COLUMNS: &[&str] = &[
+    "id",
+    "name",
+    "name_kk",
+    "sku",
+    "barcodes",
+    "category",
+    "category_name",
+    "category_name_kk",
+    "price",
+    "old_price",
+    "unit",
+    "stock",
+    "description",
+    "description_kk",
+    "is_hidden",
+    "created_at",
+    "updated_at",
+];
+
+const CATEGORY_COLUMNS: &[&str] = &[
+    "id",
+    "parent_id",
+    "slug",
+    "name",
+    "name_kk",
+    "image",
+    "created_at",
+    "updated_at",
+];
+
+const ORDER_COLUMNS: &[&str] = &[
+    "id",
+    "order_number",
+    "created_at",
+    "status",
+    "customer_name",
+    "customer_phone",
+    "address",
+    "comment",
+    "promo_code",
+    "delivery_date",
+    "delivery_fee",
+    "total_price",
+    "items",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+enum Source {
+    // Категории товаров подставляются по category_id: slug и названия
+    Products(HashMap<i32, Category>),
+    Categories,
+    Orders,
+}
+
+impl Source {
+    fn table(&self) -> &'static str {
+        match self {
+            Source::Products(_) => "products",
+            Source::Categories => "categories",
+            Source::Orders => "orders",
+        }
+    }
+
+    fn columns(&self) -> &'static [&'static str] {
+        match self {
+            Source::Products(_) => PRODUCT_COLUMNS,
+            Source::Categories => CATEGORY_COLUMNS,
+            Source::Orders => ORDER_COLUMNS,
+        }
+    }
+
+    async fn records(&self, d1: &D1Database, result: &D1Result) -> Result<Vec<serde_json::Value>> {
+        match self {
+            Source::Products(categories) => {
+                let mut products = result.results::<Product>()?;
+                products::attach_barcodes(d1, &mut products).await?;
+                products
+                    .into_iter()
+                    .map(|product| {
+                        let category = product.category_id.and_then(|id| categories.get(&id));
+                        let mut value = serde_json::to_value(&product)?;
+                        value["category"] = category.and_then(|c| c.slug.clone()).into();
+                        value["category_name"] = category.map(|c| c.name.clone()).into();
+                        value["category_name_kk"] = category.map(|c| c.name_kk.clone()).into();
+                        Ok(value)
+                    })
+                    .collect()
+            }
+            Source::Categories => result
+                .results::<Category>()?
+                .iter()
+                .map(|c| Ok(serde_json::to_value(c)?))
+                .collect(),
+            Source::Orders => result
+                .results::<Order>()?
+                .iter()
+                .map(|order| {
+                    let mut value = serde_json::to_value(order)?;
+                    let items: Vec<serde_json::Value> =
+                        serde_json::from_str(&order.items_json).unwrap_or_default();
+                    value["items"] = items.into();
+                    Ok(value)
+                })
+                .collect(),
+        }
+    }
+}
+
+// Ячейка CSV. Позиции заказа сворачиваются в "Молоко × 2; Хлеб × 1",
+// штрихкоды — в "4870001234560; 4870001234577"
+fn cell(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .map(
+                |item| match (item["name"].as_str(), item["quantity"].as_f64()) {
+                    (Some(name), Some(quantity)) => format!("{} × {}", name, quantity),
+                    _ => cell(item),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join("; "),
+        other => other.to_string(),
+    }
+}
+
+struct Export {
+    env: Env,
+    source: Source,
+    format: Format,
+    // " WHERE ..." с фильтрами списка
+    where_sql: String,
+    params: Vec<wasm_bindgen::JsValue>,
+    last_id: i32,
+    written: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl Export {
+    // Очередная порция выгрузки: начало файла, страница записей, конец файла
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let mut out = String::new();
+        if !self.started {
+            self.started = true;
+            match self.format {
+                // BOM, чтобы Excel открыл файл в UTF-8
+                Format::Csv => {
+                    out.push('\u{feff}');
+                    out.push_str(&csv::row(self.source.columns(), DELIMITER));
+                }
+                Format::Json => out.push('['),
+            }
+        }
+
+        let mut params = self.params.clone();
+        params.push(self.last_id.into());
+        params.push(PAGE_SIZE.into());
+        let d1 = self.env.d1("akniet_db")?;
+        let result = d1
+            .prepare(format!(
+                "SELECT * FROM {}{} AND id > ? ORDER BY id LIMIT ?",
+                self.source.table(),
+                self.where_sql
+            ))
+            .bind(&params)?
+            .all()
+            .await?;
+        let records = self.source.records(&d1, &result).await?;
+
+        for record in &records {
+            self.last_id = record["id"].as_i64().unwrap_or_default() as i32;
+            match self.format {
+                Format::Csv => {
+                    let values: Vec<String> = self
+                        .source
+                        .columns()
+                        .iter()
+                        .map(|column| cell(&record[*column]))
+                        .collect();
+                    out.push_str(&csv::row(&values, DELIMITER));
+                }
+                Format::Json => {
+                    if self.written > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&record.to_string());
+                }
+            }
+            self.written += 1;
+        }
+
+        if (records.len() as i32) < PAGE_SIZE {
+            self.finished = true;
+            if self.format == Format::Json {
+                out.push(']');
+            }
+        }
+        Ok(Some(out.into_bytes()))
+    }
+}
+
+fn stream_export(name: &str, export: Export) -> Result<Response> {
+    let (content_type, extension) = match export.format {
+        Format::Csv => ("text/csv; charset=utf-8", "csv"),
+        Format::Json => ("application/json; charset=utf-8", "json"),
+    };
+    let file_name = format!("{}-{}.{}", name, clock::today(), extension);
+
+    let body = stream::try_unfold(export, |mut export| async move {
+        Ok::<_, Error>(export.next_chunk().await?.map(|chunk| (chunk, export)))
+    });
+
+    let mut response = Response::from_stream(body)?;
+    let headers = response.headers_mut();
+    headers.set("Content-Type", content_type)?;
+    headers.set(
+        "Content-Disposition",
+        &format!("attachment; filename=\"{}\"", file_name),
+    )?;
+    Ok(response)
+}
+
+fn query_pairs(req: &Request) -> Result<Vec<(String, String)>> {
+    Ok(req.url()?.query_pairs().into_owned().collect())
+}
+
+// ?format=csv (по умолчанию) или json
+fn export_format(query_pairs: &[(String, String)]) -> Option<Format> {
+    match query_pairs
+        .iter()
+        .find(|(k, _)| k == "format")
+        .map(|(_, v)| v.as_str())
+    {
+        None | Some("csv") => Some(Format::Csv),
+        Some("json") => Some(Format::Json),
+        _ => None,
+    }
+}
+
+fn new_export(
+    ctx: &RouteContext<Context>,
+    source: Source,
+    format: Format,
+    where_sql: String,
+    params: Vec<wasm_bindgen::JsValue>,
+) -> Export {
+    Export {
+        env: ctx.env.clone(),
+        source,
+        format,
+        where_sql,
+        params,
+        last_id: 0,
+        written: 0,
+        started: false,
+        finished: false,
+    }
+}
+
+// Товары с названиями категорий. Фильтры как у GET /api/products (admin, categoryId, q)
+pub async fn export_products(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let query_pairs = query_pairs(&req)?;
+    let Some(format) = export_format(&query_pairs) else {
+        return Response::error("format: csv или json", 400);
+    };
+
+    let is_admin = query_pairs.iter().any(|(k, v)| k == "admin" && v == "true");
+    let mut where_sql = if is_admin {
+        " WHERE 1=1".to_string()
+    } else {
+        format!(" WHERE {}", products::STOREFRONT_VISIBLE)
+    };
+    let (filter_sql, params) = products::product_filters(&query_pairs);
+    where_sql.push_str(&filter_sql);
+
+    let d1 = ctx.env.d1("akniet_db")?;
+    let categories: HashMap<i32, Category> = d1
+        .prepare("SELECT * FROM categories")
+        .all()
+        .await?
+        .results::<Category>()?
+        .into_iter()
+        .map(|c| (c.id, c))
+        .collect();
+
+    let export = new_export(
+        &ctx,
+        Source::Products(categories),
+        format,
+        where_sql,
+        params,
+    );
+    stream_export("products", export)
+}
+
+pub async fn export_categories(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let query_pairs = query_pairs(&req)?;
+    let Some(format) = export_format(&query_pairs) else {
+        return Response::error("format: csv или json", 400);
+    };
+
+    let export = new_export(
+        &ctx,
+        Source::Categories,
+        format,
+        " WHERE 1=1".to_string(),
+        Vec::new(),
+    );
+    stream_export("categories", export)
+}
+
+// Заказы с позициями. Фильтры как у GET /api/orders (даты, статус, сумма, промокод, телефон...)
+pub async fn export_orders(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let query_pairs = query_pairs(&req)?;
+    let Some(format) = export_format(&query_pairs) else {
+        return Response::error("format: csv или json", 400);
+    };
+
+    let (mut where_sql, mut params) = orders::order_filters(&query_pairs);
+    let (status_sql, status_params) = orders::status_filter(&query_pairs);
+    where_sql.push_str(&status_sql);
+    params.extend(status_params);
+
+    let export = new_export(&ctx, Source::Orders, format, where_sql, params);
+    stream_export("orders", export)
+}
@@ -0,0 +1,48 @@
+use crate::feeds::{self, Feed};
+use worker::*;
+
+// Маркетплейсы забирают фид раз в несколько часов; файл обновляется по cron
+const CACHE_CONTROL: &str = "public, max-age=3600";
+
+async fn serve(ctx: RouteContext<Context>, feed: Feed) -> Result<Response> {
+    let bucket = ctx.env.bucket("akniet_bucket")?;
+
+    let xml = match bucket.get(feed.key()).execute().await? {
+        Some(object) => match object.body() {
+            Some(body) => body.text().await?,
+            None => feeds::build(&ctx.env, feed).await?,
+        },
+        None => feeds::build(&ctx.env, feed).await?,
+    };
+
+    let mut response = Response::ok(xml)?;
+    let headers = response.headers_mut();
+    headers.set("Content-Type", "application/xml; charset=utf-8")?;
+    headers.set("Cache-Control", CACHE_CONTROL)?;
+    Ok(response)
+}
+
+// Яндекс Маркет (YML)
+pub async fn yandex_feed(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    serve(ctx, Feed::Yandex).await
+}
+
+// Google Merchant Center (RSS 2.0)
+pub async fn google_feed(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    serve(ctx, Feed::Google).await
+}
+
+// Пересобрать фиды сразу, не дожидаясь cron (например, после импорта)
+pub async fn regenerate_feeds(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let counts = feeds::regenerate(&ctx.env).await?;
+    let report: serde_json::Map<String, serde_json::Value> = counts
+        .into_iter()
+        .map(|(feed, count)| {
+            (
+                feed.as_str().to_string(),
+                serde_json::json!({ "key": feed.key(), "items": count }),
+            )
+        })
+        .collect();
+    Response::from_json(&report)
+}
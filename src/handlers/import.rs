@@ -0,0 +1,630 @@
+use crate::csv;
+use crate::handlers::attributes;
+use crate::handlers::inventory::{self, MovementReason};
+use crate::handlers::products;
+use crate::jobs::{self, Job};
+use crate::models::{Category, Product, ProductImport};
+use crate::validation;
+use std::collections::HashMap;
+use worker::*;
+
+// Не больше строк за один импорт (все изменения уходят одним batch)
+const MAX_ROWS: usize = 1000;
+
+// Поля товара, которые можно загрузить из файла. category — slug категории,
+// barcodes — один или несколько штрихкодов через запятую или точку с запятой
+const FIELDS: [&str; 12] = [
+    "id",
+    "sku",
+    "barcodes",
+    "name",
+    "name_kk",
+    "price",
+    "old_price",
+    "unit",
+    "category",
+    "description",
+    "description_kk",
+    "stock",
+];
+
+// Способы сопоставления строки файла с товаром в базе
+const MATCH_BY: [&str; 4] = ["id", "name", "sku", "barcode"];
+
+// "1 234,50" -> 1234.5
+fn parse_number(value: &str) -> Option<f64> {
+    let cleaned: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '\u{a0}')
+        .map(|c| if c == ',' { '.' } else { c })
+        .collect();
+    cleaned.parse::<f64>().ok().filter(|n| *n >= 0.0)
+}
+
+// Изменение одной колонки товара
+struct Change {
+    column: &'static str,
+    from: serde_json::Value,
+    to: serde_json::Value,
+}
+
+// Строка файла после проверки
+struct RowPlan {
+    line: usize,
+    existing: Option<i32>,
+    name: String,
+    changes: Vec<Change>,
+    errors: Vec<String>,
+}
+
+impl RowPlan {
+    fn status(&self) -> &'static str {
+        if !self.errors.is_empty() {
+            "error"
+        } else if self.existing.is_none() {
+            "new"
+        } else if self.changes.is_empty() {
+            "unchanged"
+        } else {
+            "changed"
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let changes: serde_json::Map<String, serde_json::Value> = self
+            .changes
+            .iter()
+            .map(|c| {
+                (
+                    c.column.to_string(),
+                    serde_json::json!({ "from": c.from, "to": c.to }),
+                )
+            })
+            .collect();
+        serde_json::json!({
+            "line": self.line,
+            "status": self.status(),
+            "id": self.existing,
+            "name": self.name,
+            "changes": changes,
+            "errors": self.errors,
+        })
+    }
+
+    fn barcodes(&self) -> Option<Vec<String>> {
+        serde_json::from_value(self.value("barcodes")?.clone()).ok()
+    }
+
+    fn value(&self, column: &str) -> Option<&serde_json::Value> {
+        self.changes
+            .iter()
+            .find(|c| c.column == column)
+            .map(|c| &c.to)
+    }
+}
+
+fn text_change(
+    changes: &mut Vec<Change>,
+    column: &'static str,
+    current: Option<&str>,
+    value: &str,
+) {
+    if current.unwrap_or_default() != value {
+        changes.push(Change {
+            column,
+            from: current.into(),
+            to: value.into(),
+        });
+    }
+}
+
+fn number_change(
+    changes: &mut Vec<Change>,
+    column: &'static str,
+    current: Option<f64>,
+    value: f64,
+) {
+    if current.is_none_or(|c| (c - value).abs() > f64::EPSILON) {
+        changes.push(Change {
+            column,
+            from: current.into(),
+            to: value.into(),
+        });
+    }
+}
+
+fn js_value(value: &serde_json::Value) -> wasm_bindgen::JsValue {
+    match value {
+        serde_json::Value::Number(n) => n.as_f64().unwrap_or_default().into(),
+        serde_json::Value::String(s) => s.as_str().into(),
+        _ => wasm_bindgen::JsValue::NULL,
+    }
+}
+
+// Импорт товаров из CSV (multipart): file, mapping? ({ "price": "Цена", ... }),
+// match_by? (id | name | sku | barcode), dry_run? (по умолчанию true), skip_errors?, actor?.
+// Пустая ячейка у существующего товара означает "не менять"
+pub async fn import_products(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let form = req.form_data().await?;
+
+    let Some(FormEntry::File(file)) = form.get("file") else {
+        return Response::error("Прикрепите файл (поле file)", 400);
+    };
+    let file_name = file.name();
+    let lower_name = file_name.to_lowercase();
+    if lower_name.ends_with(".xlsx") || lower_name.ends_with(".xls") {
+        return Response::error("Поддерживается только CSV: сохраните таблицу как CSV", 415);
+    }
+    let text = String::from_utf8_lossy(&file.bytes().await?).to_string();
+
+    let field = |name: &str| form.get_field(name).map(|v| v.trim().to_string());
+    let flag = |name: &str, default: bool| {
+        field(name)
+            .map(|v| matches!(v.as_str(), "true" | "1" | "yes"))
+            .unwrap_or(default)
+    };
+    let dry_run = flag("dry_run", true);
+    let skip_errors = flag("skip_errors", false);
+    let actor = field("actor")
+        .filter(|a| !a.is_empty())
+        .unwrap_or_else(|| "admin".to_string());
+
+    let rows = csv::parse(&text);
+    let Some((header, data)) = rows.split_first() else {
+        return Response::error("Файл пустой", 400);
+    };
+    if data.len() > MAX_ROWS {
+        return Response::error(format!("Не больше {} строк за один импорт", MAX_ROWS), 400);
+    }
+
+    // Колонки файла: по mapping { поле: заголовок } или по совпадению заголовка с именем поля
+    let mapping: serde_json::Map<String, serde_json::Value> = match field("mapping") {
+        Some(json) if !json.is_empty() => match serde_json::from_str(&json) {
+            Ok(mapping) => mapping,
+            Err(_) => return Response::error("mapping должен быть JSON-объектом", 400),
+        },
+        _ => serde_json::Map::new(),
+    };
+    let find_column = |title: &str| {
+        header
+            .iter()
+            .position(|h| h.trim().to_lowercase() == title.trim().to_lowercase())
+    };
+    let mut columns: HashMap<&'static str, usize> = HashMap::new();
+    for name in FIELDS {
+        let column = match mapping.get(name).and_then(|v| v.as_str()) {
+            Some(title) => match find_column(title) {
+                Some(column) => Some(column),
+                None => return Response::error(format!("Колонка \"{}\" не найдена", title), 400),
+            },
+            // Колонку со штрихкодом часто называют в единственном числе
+            None if name == "barcodes" => find_column(name).or_else(|| find_column("barcode")),
+            None => find_column(name),
+        };
+        if let Some(column) = column {
+            columns.insert(name, column);
+        }
+    }
+
+    let match_by = field("match_by")
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| {
+            if columns.contains_key("id") {
+                "id"
+            } else if columns.contains_key("sku") {
+                "sku"
+            } else {
+                "name"
+            }
+            .to_string()
+        });
+    if !MATCH_BY.contains(&match_by.as_str()) {
+        return Response::error("match_by: id, name, sku или barcode", 400);
+    }
+    let key_field = if match_by == "barcode" {
+        "barcodes"
+    } else {
+        match_by.as_str()
+    };
+    if !columns.contains_key(key_field) {
+        return Response::error(format!("В файле нет колонки для {}", match_by), 400);
+    }
+
+    let results = d1
+        .batch(vec![
+            d1.prepare("SELECT * FROM products"),
+            d1.prepare("SELECT * FROM categories"),
+        ])
+        .await?;
+    let products = results[0].results::<Product>()?;
+    let categories = results[1].results::<Category>()?;
+
+    let by_id: HashMap<i32, &Product> = products.iter().map(|p| (p.id, p)).collect();
+    let by_name: HashMap<String, &Product> = products
+        .iter()
+        .map(|p| (p.name.trim().to_lowercase(), p))
+        .collect();
+    let by_sku: HashMap<String, &Product> = products
+        .iter()
+        .filter_map(|p| Some((p.sku.as_deref()?.to_lowercase(), p)))
+        .collect();
+    let ids: Vec<i32> = products.iter().map(|p| p.id).collect();
+    let barcodes = products::load_barcodes(&d1, &ids).await?;
+    let by_barcode: HashMap<&str, &Product> = barcodes
+        .iter()
+        .filter_map(|(id, codes)| Some((codes, *by_id.get(id)?)))
+        .flat_map(|(codes, product)| codes.iter().map(move |code| (code.as_str(), product)))
+        .collect();
+    let category_by_slug: HashMap<String, i32> = categories
+        .iter()
+        .filter_map(|c| Some((c.slug.as_deref()?.trim().to_lowercase(), c.id)))
+        .collect();
+
+    let mut plans: Vec<RowPlan> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut seen_skus: HashMap<String, usize> = HashMap::new();
+    let mut seen_barcodes: HashMap<String, usize> = HashMap::new();
+    // Новые товары без ключа сопоставления различаем по названию
+    let mut seen_new_names: HashMap<String, usize> = HashMap::new();
+
+    for (index, row) in data.iter().enumerate() {
+        let line = index + 2;
+        let cell = |name: &str| {
+            columns
+                .get(name)
+                .and_then(|&i| row.get(i))
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+
+        let mut errors = Vec::new();
+        let raw_key = cell(key_field).unwrap_or_default();
+        // По штрихкоду сопоставляем первый код из ячейки
+        let key = match match_by.as_str() {
+            "barcode" => validation::parse_barcodes([raw_key.as_str()])
+                .ok()
+                .and_then(|codes| codes.into_iter().next())
+                .unwrap_or(raw_key),
+            _ => raw_key.to_lowercase(),
+        };
+        let existing = match match_by.as_str() {
+            "id" => key.parse::<i32>().ok().and_then(|id| by_id.get(&id)),
+            "sku" => by_sku.get(&key),
+            "barcode" => by_barcode.get(key.as_str()),
+            _ => by_name.get(&key),
+        }
+        .copied();
+        let existing_id = existing.map(|p| p.id);
+
+        // Строка без ключа (id, артикула, штрихкода или названия) — новый товар
+        if !key.is_empty() {
+            if let Some(first) = seen.insert(key.clone(), line) {
+                errors.push(format!("Повторяет строку {}", first));
+            }
+            if match_by == "id" && existing.is_none() {
+                errors.push(format!("Товар с id {} не найден", key));
+            }
+        }
+
+        let mut changes = Vec::new();
+        let name = cell("name").unwrap_or_default();
+        if !name.is_empty() {
+            text_change(
+                &mut changes,
+                "name",
+                existing.map(|p| p.name.as_str()),
+                &name,
+            );
+        } else if existing.is_none() {
+            errors.push("Не указано название".to_string());
+        }
+        if existing.is_none() && match_by != "name" && !name.is_empty() {
+            if let Some(first) = seen_new_names.insert(name.to_lowercase(), line) {
+                errors.push(format!(
+                    "Новый товар с тем же названием, что в строке {}",
+                    first
+                ));
+            }
+        }
+
+        for (column, current) in [
+            ("name_kk", existing.and_then(|p| p.name_kk.as_deref())),
+            ("unit", existing.and_then(|p| p.unit.as_deref())),
+            (
+                "description",
+                existing.and_then(|p| p.description.as_deref()),
+            ),
+            (
+                "description_kk",
+                existing.and_then(|p| p.description_kk.as_deref()),
+            ),
+        ] {
+            if let Some(value) = cell(column) {
+                text_change(&mut changes, column, current, &value);
+            }
+        }
+
+        for (column, current) in [
+            ("price", existing.map(|p| p.price)),
+            ("old_price", existing.and_then(|p| p.old_price)),
+            ("stock", existing.and_then(|p| p.stock)),
+        ] {
+            match cell(column).map(|v| (parse_number(&v), v)) {
+                Some((Some(value), _)) => number_change(&mut changes, column, current, value),
+                Some((None, raw)) => errors.push(format!("{}: неверное число \"{}\"", column, raw)),
+                None if column == "price" && existing.is_none() => {
+                    errors.push("Не указана цена".to_string())
+                }
+                None => {}
+            }
+        }
+
+        if let Some(slug) = cell("category") {
+            match category_by_slug.get(&slug.to_lowercase()) {
+                Some(&category_id) => {
+                    let current = existing.and_then(|p| p.category_id);
+                    if current != Some(category_id) {
+                        changes.push(Change {
+                            column: "category_id",
+                            from: current.into(),
+                            to: category_id.into(),
+                        });
+                    }
+                }
+                None => errors.push(format!("Категория \"{}\" не найдена", slug)),
+            }
+        }
+
+        if let Some(raw) = cell("sku") {
+            match validation::normalize_sku(&raw) {
+                Some(sku) => {
+                    let lower = sku.to_lowercase();
+                    if let Some(owner) = by_sku.get(&lower).filter(|p| Some(p.id) != existing_id) {
+                        errors.push(format!("Артикул {} уже есть у товара {}", sku, owner.id));
+                    } else if match_by != "sku" {
+                        if let Some(first) = seen_skus.insert(lower, line) {
+                            errors.push(format!("Артикул {} повторяет строку {}", sku, first));
+                        }
+                    }
+                    text_change(
+                        &mut changes,
+                        "sku",
+                        existing.and_then(|p| p.sku.as_deref()),
+                        &sku,
+                    );
+                }
+                None => errors.push(format!("Неверный артикул \"{}\"", raw)),
+            }
+        }
+
+        if let Some(raw) = cell("barcodes") {
+            match validation::parse_barcodes([raw.as_str()]) {
+                Ok(codes) => {
+                    for code in &codes {
+                        if let Some(owner) = by_barcode
+                            .get(code.as_str())
+                            .filter(|p| Some(p.id) != existing_id)
+                        {
+                            errors
+                                .push(format!("Штрихкод {} уже есть у товара {}", code, owner.id));
+                        } else if match_by != "barcode" || code != &key {
+                            if let Some(first) = seen_barcodes.insert(code.clone(), line) {
+                                errors
+                                    .push(format!("Штрихкод {} повторяет строку {}", code, first));
+                            }
+                        }
+                    }
+                    let current = existing_id
+                        .and_then(|id| barcodes.get(&id))
+                        .cloned()
+                        .unwrap_or_default();
+                    let (mut before, mut after) = (current.clone(), codes.clone());
+                    before.sort();
+                    after.sort();
+                    if before != after {
+                        changes.push(Change {
+                            column: "barcodes",
+                            from: current.into(),
+                            to: codes.into(),
+                        });
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        plans.push(RowPlan {
+            line,
+            existing: existing_id,
+            name: if name.is_empty() {
+                existing.map(|p| p.name.clone()).unwrap_or_default()
+            } else {
+                name
+            },
+            changes,
+            errors,
+        });
+    }
+
+    let count = |status: &str| plans.iter().filter(|p| p.status() == status).count();
+    let (created, updated, unchanged, failed) = (
+        count("new"),
+        count("changed"),
+        count("unchanged"),
+        count("error"),
+    );
+
+    let committed = !dry_run && (failed == 0 || skip_errors);
+    let mut queries = Vec::new();
+    let mut restocked = Vec::new();
+    if committed {
+        let note = format!("Импорт {}", file_name);
+        for plan in plans.iter().filter(|p| p.errors.is_empty()) {
+            match plan.existing {
+                None => {
+                    let value = |column: &str| {
+                        plan.value(column)
+                            .map(js_value)
+                            .unwrap_or(wasm_bindgen::JsValue::NULL)
+                    };
+                    let stock = plan.value("stock").and_then(|v| v.as_f64()).unwrap_or(0.0);
+                    queries.push(
+                        d1.prepare(
+                            "INSERT INTO products (name, name_kk, sku, category_id, price, old_price, unit, image, description, description_kk, stock, created_at, updated_at)
+                             VALUES (?, ?, ?, ?, ?, ?, ?, '[]', ?, ?, ?, datetime('now'), datetime('now'))",
+                        )
+                        .bind(&[
+                            value("name"),
+                            value("name_kk"),
+                            value("sku"),
+                            value("category_id"),
+                            value("price"),
+                            value("old_price"),
+                            value("unit"),
+                            value("description"),
+                            value("description_kk"),
+                            stock.into(),
+                        ])?,
+                    );
+                    queries.push(inventory::initial_stock(&d1, stock, Some(&actor))?);
+                    if let Some(codes) = plan.barcodes() {
+                        queries.extend(products::barcode_statements(&d1, None, &codes)?);
+                    }
+                }
+                Some(id) if !plan.changes.is_empty() => {
+                    let columns: Vec<&Change> = plan
+                        .changes
+                        .iter()
+                        .filter(|c| c.column != "stock" && c.column != "barcodes")
+                        .collect();
+                    if !columns.is_empty() {
+                        let sets = columns
+                            .iter()
+                            .map(|c| format!("{} = ?", c.column))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        let mut params: Vec<wasm_bindgen::JsValue> =
+                            columns.iter().map(|c| js_value(&c.to)).collect();
+                        params.push(id.into());
+                        queries.push(
+                            d1.prepare(format!(
+                                "UPDATE products SET {}, version = version + 1, updated_at = datetime('now') WHERE id = ?",
+                                sets
+                            ))
+                            .bind(&params)?,
+                        );
+                        if columns.iter().any(|c| c.column == "category_id") {
+                            queries.push(attributes::purge_foreign_values(&d1, &[id])?);
+                        }
+                    }
+                    if let Some(codes) = plan.barcodes() {
+                        queries.extend(products::barcode_statements(&d1, Some(id), &codes)?);
+                    }
+                    if let Some(stock) = plan.value("stock").and_then(|v| v.as_f64()) {
+                        queries.extend(inventory::set_stock(
+                            &d1,
+                            id,
+                            stock,
+                            None,
+                            MovementReason::Correction,
+                            Some(&actor),
+                            Some(&note),
+                        )?);
+                        restocked.push(id);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    let errors: Vec<serde_json::Value> = plans
+        .iter()
+        .filter(|p| !p.errors.is_empty())
+        .map(|p| serde_json::json!({ "line": p.line, "errors": p.errors }))
+        .collect();
+    queries.push(
+        d1.prepare(
+            "INSERT INTO product_imports (file_name, actor, dry_run, committed, match_by, total_rows, created_count, updated_count, unchanged_count, error_count, errors_json, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'))",
+        )
+        .bind(&[
+            file_name.clone().into(),
+            actor.clone().into(),
+            (dry_run as i32).into(),
+            (committed as i32).into(),
+            match_by.clone().into(),
+            (plans.len() as i32).into(),
+            (created as i32).into(),
+            (updated as i32).into(),
+            (unchanged as i32).into(),
+            (failed as i32).into(),
+            serde_json::Value::Array(errors).to_string().into(),
+        ])?,
+    );
+    d1.batch(queries).await?;
+
+    if !restocked.is_empty() {
+        jobs::enqueue(
+            &ctx,
+            vec![Job::StockCheck {
+                product_ids: restocked,
+            }],
+        );
+    }
+
+    let report = serde_json::json!({
+        "file_name": file_name,
+        "dry_run": dry_run,
+        "committed": committed,
+        "match_by": match_by,
+        "summary": {
+            "total": plans.len(),
+            "new": created,
+            "changed": updated,
+            "unchanged": unchanged,
+            "errors": failed,
+        },
+        "rows": plans.iter().map(|p| p.to_json()).collect::<Vec<_>>(),
+    });
+
+    // Есть ошибки, а пропускать их не разрешили — ничего не меняем
+    if !dry_run && !committed {
+        return Ok(Response::from_json(&report)?.with_status(422));
+    }
+    Response::from_json(&report)
+}
+
+// История импортов (последние 50)
+pub async fn import_history(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let imports = d1
+        .prepare("SELECT * FROM product_imports ORDER BY id DESC LIMIT 50")
+        .all()
+        .await?
+        .results::<ProductImport>()?;
+    Response::from_json(&imports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_numbers_from_spreadsheets() {
+        assert_eq!(parse_number("990"), Some(990.0));
+        assert_eq!(parse_number("1 290,50"), Some(1290.5));
+        assert_eq!(parse_number("12\u{a0}345"), Some(12345.0));
+        assert_eq!(parse_number(" 0.5 "), Some(0.5));
+        assert_eq!(parse_number("0"), Some(0.0));
+    }
+
+    #[test]
+    fn rejects_bad_numbers() {
+        assert_eq!(parse_number(""), None);
+        assert_eq!(parse_number("abc"), None);
+        assert_eq!(parse_number("-5"), None);
+        assert_eq!(parse_number("1,2,3"), None);
+    }
+}
@@ -0,0 +1,558 @@
+use crate::jobs::{self, Job};
+use crate::models::{Product, StockMovementEntry};
+use std::collections::HashMap;
+use worker::*;
+
+// Причина движения товара в журнале stock_movements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementReason {
+    Sale,
+    Restock,
+    Correction,
+    Cancellation,
+    WriteOff,
+}
+
+impl MovementReason {
+    pub const ALL: [MovementReason; 5] = [
+        MovementReason::Sale,
+        MovementReason::Restock,
+        MovementReason::Correction,
+        MovementReason::Cancellation,
+        MovementReason::WriteOff,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MovementReason::Sale => "sale",
+            MovementReason::Restock => "restock",
+            MovementReason::Correction => "correction",
+            MovementReason::Cancellation => "cancellation",
+            MovementReason::WriteOff => "write_off",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|r| r.as_str() == value)
+    }
+
+    // Движение по заказу: списание — продажа, возврат на склад — отмена
+    pub fn for_order(delta: f64) -> Self {
+        if delta < 0.0 {
+            MovementReason::Sale
+        } else {
+            MovementReason::Cancellation
+        }
+    }
+}
+
+// Изменение остатка товара с записью в журнал
+pub struct StockMovement {
+    pub product_id: i32,
+    pub delta: f64,
+    pub reason: MovementReason,
+    pub actor: Option<String>,
+    pub order_id: Option<i32>,
+    // Для нового заказа, id которого в batch ещё неизвестен: последний вставленный заказ
+    pub new_order: bool,
+    pub note: Option<String>,
+    // Для приёмки товара
+    pub supplier: Option<String>,
+    pub unit_cost: Option<f64>,
+    // Не уводить остаток в минус: при нехватке UPDATE ничего не меняет
+    pub require_stock: bool,
+}
+
+impl StockMovement {
+    pub fn new(product_id: i32, delta: f64, reason: MovementReason) -> Self {
+        StockMovement {
+            product_id,
+            delta,
+            reason,
+            actor: None,
+            order_id: None,
+            new_order: false,
+            note: None,
+            supplier: None,
+            unit_cost: None,
+            require_stock: false,
+        }
+    }
+
+    // Два запроса для одного batch: UPDATE остатка и запись в журнал.
+    // Запись появляется, только если UPDATE изменил строку (changes() > 0),
+    // balance — остаток сразу после изменения
+    pub fn statements(&self, d1: &D1Database) -> Result<[D1PreparedStatement; 2]> {
+        let update = d1
+            .prepare("UPDATE products SET stock = stock + ?1 WHERE id = ?2 AND (?3 = 0 OR stock + ?1 >= 0)")
+            .bind(&[
+                self.delta.into(),
+                self.product_id.into(),
+                (self.require_stock as i32).into(),
+            ])?;
+        let log = d1
+            .prepare(
+                "INSERT INTO stock_movements (product_id, reason, delta, balance, actor, order_id, note, supplier, unit_cost, created_at)
+                 SELECT id, ?, ?, stock, ?, COALESCE(?, CASE WHEN ? THEN (SELECT MAX(id) FROM orders) END), ?, ?, ?, datetime('now')
+                 FROM products WHERE id = ? AND changes() > 0",
+            )
+            .bind(&[
+                self.reason.as_str().into(),
+                self.delta.into(),
+                self.actor.clone().into(),
+                self.order_id.into(),
+                (self.new_order as i32).into(),
+                self.note.clone().into(),
+                self.supplier.clone().into(),
+                self.unit_cost.into(),
+                self.product_id.into(),
+            ])?;
+        Ok([update, log])
+    }
+}
+
+// Идёт в batch сразу после запроса, который обязан изменить строку: после statements()
+// движения с require_stock (остатка не хватило — запись в журнал не появилась),
+// после set_stock с expected или версионного UPDATE товара. Иначе CHECK откатывает весь batch
+pub fn stock_guard(d1: &D1Database) -> D1PreparedStatement {
+    d1.prepare("INSERT INTO stock_guard (ok) SELECT 0 WHERE changes() = 0")
+}
+
+pub fn is_stock_shortage(e: &Error) -> bool {
+    e.to_string()
+        .contains("CHECK constraint failed: stock_available")
+}
+
+// Установка остатка в абсолютное значение: запись в журнал с разницей
+// (только если остаток действительно меняется) и сам UPDATE.
+// expected — остаток, который видел администратор: если он уже другой, ничего не меняется
+pub fn set_stock(
+    d1: &D1Database,
+    product_id: i32,
+    stock: f64,
+    expected: Option<f64>,
+    reason: MovementReason,
+    actor: Option<&str>,
+    note: Option<&str>,
+) -> Result<[D1PreparedStatement; 2]> {
+    let log = d1
+        .prepare(
+            "INSERT INTO stock_movements (product_id, reason, delta, balance, actor, note, created_at)
+             SELECT id, ?1, ?2 - COALESCE(stock, 0), ?2, ?3, ?4, datetime('now')
+             FROM products WHERE id = ?5 AND stock IS NOT ?2 AND (?6 IS NULL OR stock IS ?6)",
+        )
+        .bind(&[
+            reason.as_str().into(),
+            stock.into(),
+            actor.into(),
+            note.into(),
+            product_id.into(),
+            expected.into(),
+        ])?;
+    let update = d1
+        .prepare("UPDATE products SET stock = ?1 WHERE id = ?2 AND (?3 IS NULL OR stock IS ?3)")
+        .bind(&[stock.into(), product_id.into(), expected.into()])?;
+    Ok([log, update])
+}
+
+// Начальный остаток только что созданного товара (идёт в batch сразу после INSERT)
+pub fn initial_stock(
+    d1: &D1Database,
+    stock: f64,
+    actor: Option<&str>,
+) -> Result<D1PreparedStatement> {
+    d1.prepare(
+        "INSERT INTO stock_movements (product_id, reason, delta, balance, actor, note, created_at)
+         SELECT id, 'restock', stock, stock, ?, 'Начальный остаток', datetime('now')
+         FROM products WHERE id = last_insert_rowid() AND ? <> 0",
+    )
+    .bind(&[actor.into(), stock.into()])
+}
+
+// Уровень нехватки товара. Порядок важен: Out хуже Low
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StockLevel {
+    Low,
+    Out,
+}
+
+impl StockLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StockLevel::Low => "low",
+            StockLevel::Out => "out",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "low" => Some(StockLevel::Low),
+            "out" => Some(StockLevel::Out),
+            _ => None,
+        }
+    }
+}
+
+// Общий порог из переменной LOW_STOCK_THRESHOLD (для товаров без своего порога)
+pub fn default_threshold(env: &Env) -> Option<f64> {
+    env.var("LOW_STOCK_THRESHOLD")
+        .ok()
+        .and_then(|v| v.to_string().trim().parse::<f64>().ok())
+        .filter(|v| *v > 0.0)
+}
+
+// Товар без учёта остатков (stock = NULL) в оповещения не попадает
+pub fn stock_level(product: &Product, default_threshold: Option<f64>) -> Option<StockLevel> {
+    let stock = product.stock?;
+    if stock <= 0.0 {
+        return Some(StockLevel::Out);
+    }
+    let threshold = product.low_stock_threshold.or(default_threshold)?;
+    (stock <= threshold).then_some(StockLevel::Low)
+}
+
+// 1. Товары, которые заканчиваются или закончились (админка)
+pub async fn low_stock(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let default = default_threshold(&ctx.env);
+
+    let products = d1
+        .prepare(
+            "SELECT * FROM products
+             WHERE stock IS NOT NULL AND (stock <= 0 OR stock <= COALESCE(low_stock_threshold, ?))
+               AND NOT EXISTS (SELECT 1 FROM products v WHERE v.parent_id = products.id)
+             ORDER BY stock ASC, name",
+        )
+        .bind(&[default.into()])?
+        .all()
+        .await?
+        .results::<Product>()?;
+
+    let mut out_of_stock = 0;
+    let items: Vec<serde_json::Value> = products
+        .iter()
+        .filter_map(|product| {
+            let level = stock_level(product, default)?;
+            if level == StockLevel::Out {
+                out_of_stock += 1;
+            }
+            Some(serde_json::json!({
+                "id": product.id,
+                "name": product.name,
+                "category_id": product.category_id,
+                "unit": product.unit,
+                "stock": product.stock,
+                "threshold": product.low_stock_threshold.or(default),
+                "level": level.as_str(),
+            }))
+        })
+        .collect();
+
+    Response::from_json(&serde_json::json!({
+        "total": items.len(),
+        "out_of_stock": out_of_stock,
+        "default_threshold": default,
+        "items": items,
+    }))
+}
+
+// 2. История движения товара: ?page=1&per_page=50&reason=sale
+pub async fn product_movements(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let url = req.url()?;
+    let query_pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    let param = |name: &str| {
+        query_pairs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.trim().to_string())
+    };
+
+    let page = param("page")
+        .and_then(|v| v.parse::<i32>().ok())
+        .unwrap_or(1)
+        .max(1);
+    let per_page = param("per_page")
+        .and_then(|v| v.parse::<i32>().ok())
+        .unwrap_or(50)
+        .clamp(1, 200);
+
+    let Some(product) = d1
+        .prepare("SELECT * FROM products WHERE id = ?")
+        .bind(&[id.clone().into()])?
+        .first::<Product>(None)
+        .await?
+    else {
+        return Response::error("Товар не найден", 404);
+    };
+
+    let mut filter = String::from("product_id = ?");
+    let mut params: Vec<wasm_bindgen::JsValue> = vec![product.id.into()];
+    if let Some(reason) = param("reason").filter(|r| !r.is_empty()) {
+        let Some(reason) = MovementReason::parse(&reason) else {
+            return Response::error("Неизвестная причина движения", 400);
+        };
+        filter.push_str(" AND reason = ?");
+        params.push(reason.as_str().into());
+    }
+
+    let mut page_params = params.clone();
+    page_params.push(per_page.into());
+    page_params.push(((page - 1) * per_page).into());
+
+    let results = d1
+        .batch(vec![
+            d1.prepare(format!(
+                "SELECT * FROM stock_movements WHERE {} ORDER BY id DESC LIMIT ? OFFSET ?",
+                filter
+            ))
+            .bind(&page_params)?,
+            d1.prepare(format!(
+                "SELECT COUNT(*) AS total FROM stock_movements WHERE {}",
+                filter
+            ))
+            .bind(&params)?,
+        ])
+        .await?;
+    let movements = results[0].results::<StockMovementEntry>()?;
+    let total = results[1]
+        .results::<serde_json::Value>()?
+        .first()
+        .and_then(|row| row["total"].as_i64())
+        .unwrap_or(0);
+
+    Response::from_json(&serde_json::json!({
+        "product_id": product.id,
+        "name": product.name,
+        "stock": product.stock,
+        "movements": movements,
+        "total": total,
+        "page": page,
+        "per_page": per_page,
+    }))
+}
+
+// Строка складской операции из тела запроса
+struct StockLine {
+    product_id: i32,
+    quantity: f64,
+    cost: Option<f64>,
+}
+
+// items: [{ product_id, <field>, cost? }]. Количество для приёмки и списания > 0,
+// для инвентаризации (allow_zero) — не меньше нуля
+fn stock_lines(
+    body: &serde_json::Value,
+    field: &str,
+    allow_zero: bool,
+) -> std::result::Result<Vec<StockLine>, String> {
+    let items = body["items"]
+        .as_array()
+        .filter(|items| !items.is_empty())
+        .ok_or("Укажите товары (items)")?;
+
+    let mut lines = Vec::new();
+    for item in items {
+        let product_id = item["product_id"]
+            .as_i64()
+            .or_else(|| item["product_id"].as_str().and_then(|s| s.parse().ok()))
+            .unwrap_or(0) as i32;
+        if product_id == 0 {
+            return Err("Не указан product_id".to_string());
+        }
+        let quantity = item[field]
+            .as_f64()
+            .filter(|q| if allow_zero { *q >= 0.0 } else { *q > 0.0 })
+            .ok_or(format!("Неверное количество для товара {}", product_id))?;
+        if lines.iter().any(|l: &StockLine| l.product_id == product_id) {
+            return Err(format!("Товар {} указан дважды", product_id));
+        }
+        lines.push(StockLine {
+            product_id,
+            quantity,
+            cost: item["cost"].as_f64().filter(|c| *c >= 0.0),
+        });
+    }
+    Ok(lines)
+}
+
+async fn load_products(d1: &D1Database, lines: &[StockLine]) -> Result<HashMap<i32, Product>> {
+    let placeholders = lines.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    let params: Vec<wasm_bindgen::JsValue> = lines.iter().map(|l| l.product_id.into()).collect();
+    let products = d1
+        .prepare(format!(
+            "SELECT * FROM products WHERE id IN ({})",
+            placeholders
+        ))
+        .bind(&params)?
+        .all()
+        .await?
+        .results::<Product>()?;
+    Ok(products.into_iter().map(|p| (p.id, p)).collect())
+}
+
+fn text_field(body: &serde_json::Value, name: &str) -> Option<String> {
+    body[name]
+        .as_str()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// Выполнение операции одним batch и ответ с новыми остатками
+async fn apply_stock_operation(
+    ctx: &RouteContext<Context>,
+    d1: &D1Database,
+    queries: Vec<D1PreparedStatement>,
+    lines: &[StockLine],
+) -> Result<Response> {
+    if let Err(e) = d1.batch(queries).await {
+        if is_stock_shortage(&e) {
+            return Response::error("Недостаточно товара на складе, остаток уже изменился", 409);
+        }
+        return Err(e);
+    }
+
+    let products = load_products(d1, lines).await?;
+    let items: Vec<serde_json::Value> = lines
+        .iter()
+        .filter_map(|l| products.get(&l.product_id))
+        .map(|p| serde_json::json!({ "product_id": p.id, "name": p.name, "stock": p.stock }))
+        .collect();
+
+    jobs::enqueue(
+        ctx,
+        vec![Job::StockCheck {
+            product_ids: lines.iter().map(|l| l.product_id).collect(),
+        }],
+    );
+    Response::from_json(&serde_json::json!({ "success": true, "items": items }))
+}
+
+// 3. Приёмка товара: { supplier?, note?, actor?, items: [{ product_id, quantity, cost? }] }
+pub async fn receive_goods(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let lines = match stock_lines(&body, "quantity", false) {
+        Ok(lines) => lines,
+        Err(e) => return Response::error(e, 400),
+    };
+    let products = load_products(&d1, &lines).await?;
+    if let Some(line) = lines.iter().find(|l| !products.contains_key(&l.product_id)) {
+        return Response::error(format!("Товар {} не найден", line.product_id), 404);
+    }
+
+    let actor = text_field(&body, "actor").unwrap_or_else(|| "admin".to_string());
+    let mut queries = Vec::new();
+    for line in &lines {
+        let movement = StockMovement {
+            actor: Some(actor.clone()),
+            note: text_field(&body, "note"),
+            supplier: text_field(&body, "supplier"),
+            unit_cost: line.cost,
+            ..StockMovement::new(line.product_id, line.quantity, MovementReason::Restock)
+        };
+        queries.extend(movement.statements(&d1)?);
+    }
+
+    apply_stock_operation(&ctx, &d1, queries, &lines).await
+}
+
+// 4. Списание: { reason, actor?, items: [{ product_id, quantity }] }
+pub async fn write_off(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let Some(reason) = text_field(&body, "reason") else {
+        return Response::error("Укажите причину списания", 400);
+    };
+    let lines = match stock_lines(&body, "quantity", false) {
+        Ok(lines) => lines,
+        Err(e) => return Response::error(e, 400),
+    };
+    let products = load_products(&d1, &lines).await?;
+    for line in &lines {
+        let Some(product) = products.get(&line.product_id) else {
+            return Response::error(format!("Товар {} не найден", line.product_id), 404);
+        };
+        let stock = product.stock.unwrap_or(0.0);
+        if stock < line.quantity {
+            return Response::error(
+                format!(
+                    "Недостаточно товара {} на складе (осталось {})",
+                    line.product_id, stock
+                ),
+                409,
+            );
+        }
+    }
+
+    let actor = text_field(&body, "actor").unwrap_or_else(|| "admin".to_string());
+    let mut queries = Vec::new();
+    for line in &lines {
+        let movement = StockMovement {
+            actor: Some(actor.clone()),
+            note: Some(reason.clone()),
+            require_stock: true,
+            ..StockMovement::new(line.product_id, -line.quantity, MovementReason::WriteOff)
+        };
+        queries.extend(movement.statements(&d1)?);
+        // Остаток успели уменьшить после проверки — весь batch откатывается с 409
+        queries.push(stock_guard(&d1));
+    }
+
+    apply_stock_operation(&ctx, &d1, queries, &lines).await
+}
+
+// 5. Инвентаризация: { note?, actor?, items: [{ product_id, counted }] }.
+// Остаток устанавливается в посчитанное значение, расхождение пишется в журнал
+pub async fn inventory_count(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let lines = match stock_lines(&body, "counted", true) {
+        Ok(lines) => lines,
+        Err(e) => return Response::error(e, 400),
+    };
+    let products = load_products(&d1, &lines).await?;
+    if let Some(line) = lines.iter().find(|l| !products.contains_key(&l.product_id)) {
+        return Response::error(format!("Товар {} не найден", line.product_id), 404);
+    }
+
+    let actor = text_field(&body, "actor").unwrap_or_else(|| "admin".to_string());
+    let note = match text_field(&body, "note") {
+        Some(note) => format!("Инвентаризация: {}", note),
+        None => "Инвентаризация".to_string(),
+    };
+
+    let mut queries = Vec::new();
+    let mut variances = Vec::new();
+    for line in &lines {
+        let previous = products[&line.product_id].stock.unwrap_or(0.0);
+        queries.extend(set_stock(
+            &d1,
+            line.product_id,
+            line.quantity,
+            None,
+            MovementReason::Correction,
+            Some(&actor),
+            Some(&note),
+        )?);
+        variances.push(serde_json::json!({
+            "product_id": line.product_id,
+            "previous": previous,
+            "counted": line.quantity,
+            "variance": line.quantity - previous,
+        }));
+    }
+
+    d1.batch(queries).await?;
+    jobs::enqueue(
+        &ctx,
+        vec![Job::StockCheck {
+            product_ids: lines.iter().map(|l| l.product_id).collect(),
+        }],
+    );
+    Response::from_json(&serde_json::json!({ "success": true, "items": variances }))
+}
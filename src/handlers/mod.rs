@@ -1,4 +1,12 @@
+pub mod attributes;
 pub mod categories;
+pub mod delivery;
+pub mod export;
+pub mod feeds;
+pub mod import;
+pub mod inventory;
 pub mod orders;
 pub mod products;
 pub mod promo;
+pub mod sitemap;
+pub mod store;
@@ -1,19 +1,277 @@
-use crate::models::Order;
+use crate::clock;
+use crate::handlers::delivery::{self, DeliveryPoint};
+use crate::handlers::inventory::{self, MovementReason, StockMovement};
+use crate::handlers::products::{resolve_variants, with_variants};
+use crate::handlers::store;
+use crate::jobs::{self, Job};
+use crate::models::{Order, OrderAuditEntry, OrderItem, OrderStatusChange, Product, ReplacedItem};
+use crate::validation::{normalize_phone, validate_customer};
+use serde::Deserialize;
+use std::collections::HashMap;
 use worker::*;
 
-// Список заказов
-pub async fn list_orders(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let d1 = ctx.env.d1("akniet_db")?;
-    let statement = d1.prepare("SELECT * FROM orders ORDER BY created_at DESC");
+#[derive(Deserialize)]
+struct NewOrder {
+    id: i32,
+    order_number: String,
+}
+
+// ID товара в позиции заказа: фронт шлёт то строку, то число
+fn item_id(item: &serde_json::Value) -> i64 {
+    json_id(&item["id"])
+}
+
+fn json_id(value: &serde_json::Value) -> i64 {
+    value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse::<i64>().ok()))
+        .unwrap_or(0)
+}
+
+fn parse_items(items_json: &str) -> Vec<OrderItem> {
+    serde_json::from_str(items_json).unwrap_or_default()
+}
+
+// Сумма заказа по позициям с учётом скидки промокода (в процентах)
+fn items_total(items: &[OrderItem], discount: i32) -> f64 {
+    let subtotal: f64 = items.iter().map(|i| i.price * i.quantity).sum();
+    let total = subtotal * (100 - discount.clamp(0, 100)) as f64 / 100.0;
+    (total * 100.0).round() / 100.0
+}
+
+// Скидка по промокоду заказа; неактивный или удалённый промокод — без скидки
+async fn promo_discount(d1: &D1Database, code: Option<&str>) -> Result<i32> {
+    let Some(code) = code else {
+        return Ok(0);
+    };
+
+    let discount = d1
+        .prepare("SELECT discount FROM promocodes WHERE UPPER(TRIM(code)) = ? LIMIT 1")
+        .bind(&[code.into()])?
+        .first::<i32>(Some("discount"))
+        .await?;
+
+    Ok(discount.unwrap_or(0))
+}
+
+// Доставка после изменения позиций: порог бесплатной доставки и минимальная сумма
+// считаются заново по зоне заказа. Без зоны (зоны не были настроены) — прежняя стоимость.
+// При сборке минимальную сумму не проверяем: отсутствующий товар всё равно не привезти
+async fn requote_delivery(
+    d1: &D1Database,
+    order: &Order,
+    goods_total: f64,
+    check_min_order: bool,
+) -> Result<std::result::Result<f64, String>> {
+    let Some(zone_id) = order.delivery_zone_id else {
+        return Ok(Ok(order.delivery_fee));
+    };
+    let Some(zone) = delivery::find_zone(d1, zone_id).await? else {
+        return Ok(Ok(order.delivery_fee));
+    };
+    Ok(if check_min_order {
+        delivery::quote(&zone, goods_total)
+    } else {
+        Ok(delivery::delivery_fee(&zone, goods_total))
+    })
+}
+
+#[derive(Deserialize)]
+struct StatusCount {
+    status: String,
+    count: i64,
+}
+
+#[derive(Deserialize)]
+struct Total {
+    total: i64,
+}
 
-    match statement.all().await {
-        Ok(result) => Response::from_json(&result.results::<Order>()?),
-        Err(e) => Response::error(format!("D1 Error: {}", e), 500),
+// Условия WHERE для списка заказов. Статус обрабатывается отдельно,
+// чтобы счётчики по вкладкам учитывали все остальные фильтры
+pub fn order_filters(query_pairs: &[(String, String)]) -> (String, Vec<wasm_bindgen::JsValue>) {
+    let mut sql = String::from(" WHERE 1=1");
+    let mut params: Vec<wasm_bindgen::JsValue> = Vec::new();
+
+    for (key, value) in query_pairs {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        match key.as_str() {
+            // Даты считаем по часовому поясу магазина, в базе время в UTC
+            "date_from" => {
+                if let Some(bound) = clock::query_bound_utc(value, false) {
+                    sql.push_str(" AND created_at >= ?");
+                    params.push(bound.into());
+                }
+            }
+            "date_to" => {
+                // Дата включительно: до начала следующего дня
+                if let Some(bound) = clock::query_bound_utc(value, true) {
+                    sql.push_str(" AND created_at < ?");
+                    params.push(bound.into());
+                }
+            }
+            "total_min" => {
+                if let Ok(total) = value.parse::<f64>() {
+                    sql.push_str(" AND total_price >= ?");
+                    params.push(total.into());
+                }
+            }
+            "total_max" => {
+                if let Ok(total) = value.parse::<f64>() {
+                    sql.push_str(" AND total_price <= ?");
+                    params.push(total.into());
+                }
+            }
+            "promo" | "promo_code" => {
+                sql.push_str(" AND promo_code = ?");
+                params.push(value.to_uppercase().into());
+            }
+            "phone" => match normalize_phone(value) {
+                Some(phone) => {
+                    sql.push_str(" AND customer_phone = ?");
+                    params.push(phone.into());
+                }
+                // Неполный номер — ищем по вхождению цифр
+                None => {
+                    let digits: String = value.chars().filter(|c| c.is_ascii_digit()).collect();
+                    if !digits.is_empty() {
+                        sql.push_str(" AND customer_phone LIKE ?");
+                        params.push(format!("%{}%", digits).into());
+                    }
+                }
+            },
+            "q" => {
+                sql.push_str(
+                    " AND (customer_name LIKE ? OR address LIKE ? OR comment LIKE ? OR order_number LIKE ?)",
+                );
+                let pattern = format!("%{}%", value);
+                for _ in 0..4 {
+                    params.push(pattern.clone().into());
+                }
+            }
+            _ => {}
+        }
     }
+
+    (sql, params)
+}
+
+// Фильтр по статусу: status=new или status=new,confirmed
+pub fn status_filter(query_pairs: &[(String, String)]) -> (String, Vec<wasm_bindgen::JsValue>) {
+    let statuses: Vec<String> = query_pairs
+        .iter()
+        .filter(|(k, _)| k == "status")
+        .flat_map(|(_, v)| v.split(','))
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if statuses.is_empty() {
+        return (String::new(), Vec::new());
+    }
+
+    let placeholders = statuses.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    let params = statuses.into_iter().map(|s| s.into()).collect();
+    (format!(" AND status IN ({})", placeholders), params)
+}
+
+// Список заказов с пагинацией и фильтрами. Ответ — массив, как и раньше;
+// общее количество и страница — в заголовках X-Total-Count, X-Page, X-Per-Page
+pub async fn list_orders(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let url = req.url()?;
+    let query_pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+
+    let param = |name: &str| {
+        query_pairs
+            .iter()
+            .find(|(k, _)| k == name)
+            .and_then(|(_, v)| v.parse::<i64>().ok())
+    };
+    let page = param("page").unwrap_or(1).max(1);
+    let per_page = param("per_page").unwrap_or(50).clamp(1, 200);
+    let Some(offset) = (page - 1)
+        .checked_mul(per_page)
+        .and_then(|offset| i32::try_from(offset).ok())
+    else {
+        return Response::error("Слишком большой номер страницы", 400);
+    };
+
+    let (mut where_sql, mut params) = order_filters(&query_pairs);
+    let (status_sql, status_params) = status_filter(&query_pairs);
+    where_sql.push_str(&status_sql);
+    params.extend(status_params);
+
+    let mut page_params = params.clone();
+    page_params.push((per_page as i32).into());
+    page_params.push(offset.into());
+
+    let results = d1
+        .batch(vec![
+            d1.prepare(format!(
+                "SELECT * FROM orders{} ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?",
+                where_sql
+            ))
+            .bind(&page_params)?,
+            d1.prepare(format!("SELECT COUNT(*) AS total FROM orders{}", where_sql))
+                .bind(&params)?,
+        ])
+        .await;
+
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => return Response::error(format!("D1 Error: {}", e), 500),
+    };
+
+    let orders = results[0].results::<Order>()?;
+    let total = results[1]
+        .results::<Total>()?
+        .first()
+        .map(|t| t.total)
+        .unwrap_or(0);
+
+    let mut response = Response::from_json(&orders)?;
+    let headers = response.headers_mut();
+    headers.set("X-Total-Count", &total.to_string())?;
+    headers.set("X-Page", &page.to_string())?;
+    headers.set("X-Per-Page", &per_page.to_string())?;
+    Ok(response)
+}
+
+// Счётчики заказов по статусам для вкладок админки. Фильтры те же, что у списка,
+// кроме самого статуса: { total, status_counts: { new: 3, ... } }
+pub async fn order_counts(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let query_pairs: Vec<(String, String)> = req.url()?.query_pairs().into_owned().collect();
+    let (where_sql, params) = order_filters(&query_pairs);
+
+    let counts = d1
+        .prepare(format!(
+            "SELECT status, COUNT(*) AS count FROM orders{} GROUP BY status",
+            where_sql
+        ))
+        .bind(&params)?
+        .all()
+        .await?
+        .results::<StatusCount>()?;
+
+    let total: i64 = counts.iter().map(|c| c.count).sum();
+    let status_counts: serde_json::Map<String, serde_json::Value> = counts
+        .into_iter()
+        .map(|c| (c.status, c.count.into()))
+        .collect();
+
+    Response::from_json(&serde_json::json!({
+        "total": total,
+        "status_counts": status_counts,
+    }))
 }
 
 // Удаление заказа
-pub async fn delete_order(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn delete_order(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
     let body: serde_json::Value = req.json().await?;
 
@@ -33,40 +291,929 @@ pub async fn delete_order(mut req: Request, ctx: RouteContext<()>) -> Result<Res
     Response::ok("Order deleted")
 }
 
+#[derive(Deserialize)]
+struct IdempotencyRecord {
+    request_body: String,
+    response_json: Option<String>,
+}
+
 //создание заказа
-pub async fn create_order(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn create_order(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
+    let idempotency_key = req
+        .headers()
+        .get("Idempotency-Key")?
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty());
     let body: serde_json::Value = req.json().await?;
 
-    let customer = &body["customer"];
-    let items = body["items"].as_array().ok_or("No items")?;
+    let Some(key) = idempotency_key else {
+        return place_order(&req, &ctx, &d1, &body).await;
+    };
+
+    // serde_json сортирует ключи, поэтому строка подходит для сравнения тел запросов
+    let request_body = body.to_string();
+
+    d1.prepare("DELETE FROM idempotency_keys WHERE created_at < datetime('now', '-24 hours')")
+        .run()
+        .await?;
+
+    // Резервируем ключ. Если он уже есть — это повтор
+    let reserved = d1
+        .prepare(
+            "INSERT INTO idempotency_keys (key, request_body, created_at) VALUES (?, ?, datetime('now'))
+             ON CONFLICT(key) DO NOTHING",
+        )
+        .bind(&[key.clone().into(), request_body.clone().into()])?
+        .run()
+        .await?
+        .meta()?
+        .and_then(|m| m.changes)
+        .unwrap_or(0)
+        > 0;
+
+    if !reserved {
+        let record = d1
+            .prepare("SELECT request_body, response_json FROM idempotency_keys WHERE key = ?")
+            .bind(&[key.into()])?
+            .first::<IdempotencyRecord>(None)
+            .await?;
+
+        return match record {
+            Some(record) if record.request_body != request_body => Response::error(
+                "Idempotency-Key уже использован с другим телом запроса",
+                422,
+            ),
+            Some(IdempotencyRecord {
+                response_json: Some(json),
+                ..
+            }) => {
+                let headers = Headers::new();
+                headers.set("Content-Type", "application/json")?;
+                headers.set("Idempotent-Replayed", "true")?;
+                Ok(Response::ok(json)?.with_headers(headers))
+            }
+            _ => Response::error("Заказ с этим Idempotency-Key ещё обрабатывается", 409),
+        };
+    }
+
+    let result = place_order(&req, &ctx, &d1, &body).await;
+
+    match result {
+        Ok(mut response) if response.status_code() == 200 => {
+            let json = response.cloned()?.text().await?;
+            let order_id = serde_json::from_str::<serde_json::Value>(&json)
+                .ok()
+                .and_then(|v| v["id"].as_i64())
+                .map(|id| id as i32);
+            d1.prepare("UPDATE idempotency_keys SET order_id = ?, response_json = ? WHERE key = ?")
+                .bind(&[order_id.into(), json.into(), key.into()])?
+                .run()
+                .await?;
+            Ok(response)
+        }
+        // Заказ не создан — освобождаем ключ, чтобы клиент мог повторить запрос
+        other => {
+            d1.prepare("DELETE FROM idempotency_keys WHERE key = ?")
+                .bind(&[key.into()])?
+                .run()
+                .await?;
+            other
+        }
+    }
+}
+
+async fn place_order(
+    req: &Request,
+    ctx: &RouteContext<Context>,
+    d1: &D1Database,
+    body: &serde_json::Value,
+) -> Result<Response> {
+    let items_in = body["items"].as_array().ok_or("No items")?;
+
+    // Проверяем имя, адрес и приводим телефон к +77XXXXXXXXX
+    let customer = match validate_customer(&body["customer"]) {
+        Ok(customer) => customer,
+        Err(e) => return Response::error(e.message(req), 400),
+    };
+
+    // Нерабочее время: в зависимости от CLOSED_ORDER_POLICY отклоняем заказ или помечаем его
+    let store = store::store_status(d1).await?;
+    let placed_while_closed = !store.is_open;
+    if placed_while_closed {
+        let policy = ctx
+            .env
+            .var("CLOSED_ORDER_POLICY")
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        if policy == "reject" {
+            let message = match store.next_open_at {
+                Some(next) => format!(
+                    "Магазин сейчас закрыт. Мы откроемся {}",
+                    next.format("%d.%m в %H:%M")
+                ),
+                None => "Магазин сейчас закрыт".to_string(),
+            };
+            return Response::error(message, 422);
+        }
+    }
+
+    let mut items: Vec<OrderItem> = Vec::new();
+    for item in items_in {
+        let line: OrderItem = serde_json::from_value(item.clone()).unwrap_or_default();
+        if line.id == 0 || line.quantity <= 0.0 {
+            return Response::error("Неверная позиция заказа", 400);
+        }
+        items.push(line);
+    }
+    if items.is_empty() {
+        return Response::error("Корзина пуста", 400);
+    }
+
+    // Цены и названия берём из базы — сумме, присланной клиентом, не доверяем
+    let placeholders = items.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    let params: Vec<wasm_bindgen::JsValue> = items.iter().map(|i| i.id.into()).collect();
+    let mut rows = d1
+        .prepare(format!(
+            "SELECT * FROM products WHERE id IN ({})",
+            placeholders
+        ))
+        .bind(&params)?
+        .all()
+        .await?
+        .results::<Product>()?;
+    // Вариант заказывается как обычный товар, название — "Молоко 1 л"
+    resolve_variants(d1, &mut rows).await?;
+    let ids: Vec<i32> = rows.iter().map(|p| p.id).collect();
+    let parents = with_variants(d1, &ids).await?;
+    let products: HashMap<i32, Product> = rows.into_iter().map(|p| (p.id, p)).collect();
+
+    for item in items.iter_mut() {
+        let Some(product) = products.get(&item.id).filter(|p| p.is_hidden == 0) else {
+            return Response::error(format!("Товар {} не найден", item.id), 400);
+        };
+        if parents.contains(&product.id) {
+            return Response::error(format!("Выберите вариант товара «{}»", product.name), 400);
+        }
+        let stock = product.stock.unwrap_or(0.0);
+        if stock < item.quantity {
+            return Response::error(
+                format!(
+                    "Недостаточно товара «{}» на складе (осталось {})",
+                    product.name, stock
+                ),
+                409,
+            );
+        }
+        item.name = product.name.clone();
+        item.price = product.price;
+    }
+
+    let promo_code = body["promo_code"]
+        .as_str()
+        .map(|c| c.trim().to_uppercase())
+        .filter(|c| !c.is_empty());
+
+    let discount = match &promo_code {
+        Some(code) => {
+            let discount = d1
+                .prepare(
+                    "SELECT discount FROM promocodes WHERE UPPER(TRIM(code)) = ? AND is_active = 1 LIMIT 1",
+                )
+                .bind(&[code.clone().into()])?
+                .first::<i32>(Some("discount"))
+                .await?;
+            match discount {
+                Some(discount) => discount,
+                None => return Response::error("Промокод не найден", 400),
+            }
+        }
+        None => 0,
+    };
+    let goods_total = items_total(&items, discount);
+
+    // Зона доставки. Пока зоны не настроены — доставка бесплатная и без ограничений
+    let zones = delivery::active_zones(d1).await?;
+    let point = DeliveryPoint::from_json(&body["customer"]);
+    let (zone_id, fee) = if zones.is_empty() {
+        (None, 0.0)
+    } else {
+        let Some(zone) = delivery::match_zone(&zones, &point) else {
+            return Response::error("Адрес вне зоны доставки", 422);
+        };
+        match delivery::quote(zone, goods_total) {
+            Ok(fee) => (zone.id, fee),
+            Err(e) => return Response::error(e, 422),
+        }
+    };
+    let total = goods_total + fee;
+
+    // Слот доставки (необязательный): { delivery_slot_id, delivery_date: "2024-09-15" }
+    let slot_id = json_id(&body["delivery_slot_id"]) as i32;
+    let delivery_date = if slot_id != 0 {
+        let date = body["delivery_date"].as_str().unwrap_or("");
+        match delivery::find_open_slot(d1, slot_id, date).await? {
+            Ok(date) => Some(date),
+            Err(e) => return Response::error(e, 400),
+        }
+    } else {
+        None
+    };
+
+    // Номер заказа вида TB-240915-0042: дата в часовом поясе магазина + порядковый номер за день.
+    // Счётчик увеличивается в одном batch с заказом: если заказ не создан, номер не сгорает
+    let day = clock::today().format("%y%m%d").to_string();
+    let counter_query = d1
+        .prepare(
+            "INSERT INTO order_counters (day, seq) VALUES (?, 1)
+             ON CONFLICT(day) DO UPDATE SET seq = seq + 1",
+        )
+        .bind(&[day.clone().into()])?;
 
     let order_query = d1.prepare(
-        "INSERT INTO orders (customer_name, customer_phone, address, comment, items_json, total_price, status, created_at) 
-         VALUES (?, ?, ?, ?, ?, ?, 'new', datetime('now'))"
+        "INSERT INTO orders (customer_name, customer_phone, address, comment, items_json, total_price, status, created_at, order_number, promo_code, delivery_zone_id, delivery_fee, latitude, longitude, delivery_slot_id, delivery_date, placed_while_closed) 
+         SELECT ?, ?, ?, ?, ?, ?, 'new', datetime('now'), 'TB-' || day || '-' || printf('%04d', seq), ?, ?, ?, ?, ?, ?, ?, ?
+         FROM order_counters WHERE day = ?
+         RETURNING id, order_number"
     ).bind(&[
-        customer["name"].as_str().unwrap_or("").into(),
-        customer["phone"].as_str().unwrap_or("").into(),
-        customer["address"].as_str().unwrap_or("").into(),
-        customer["comment"].as_str().unwrap_or("").into(),
-        body["items"].to_string().into(),
-        body["total"].as_f64().unwrap_or(0.0).into(),
+        customer.name.into(),
+        customer.phone.into(),
+        customer.address.into(),
+        customer.comment.into(),
+        serde_json::to_string(&items)?.into(),
+        total.into(),
+        promo_code.into(),
+        zone_id.into(),
+        fee.into(),
+        point.lat.into(),
+        point.lng.into(),
+        delivery_date.map(|_| slot_id).into(),
+        delivery_date.map(|d| d.to_string()).into(),
+        (placed_while_closed as i32).into(),
+        day.into(),
     ])?;
 
+    let history_query = d1.prepare(
+        "INSERT INTO order_status_history (order_id, status, created_at)
+         SELECT MAX(id), 'new', datetime('now') FROM orders",
+    );
+
     //Запросы на списание остатков
-    let mut queries = vec![order_query];
-    for item in items {
-        let id = item["id"].as_str().unwrap_or("0");
-        let qty = item["quantity"].as_f64().unwrap_or(0.0);
+    let mut queries = vec![counter_query, order_query, history_query];
+    if let Some(date) = delivery_date {
+        queries.push(delivery::reserve_slot(d1, slot_id, date)?);
+    }
+    for item in &items {
+        let movement = StockMovement {
+            actor: Some("customer".to_string()),
+            new_order: true,
+            require_stock: true,
+            ..StockMovement::new(item.id, -item.quantity, MovementReason::Sale)
+        };
+        queries.extend(movement.statements(d1)?);
+        // Остаток успели раскупить после проверки — заказ не создаётся
+        queries.push(inventory::stock_guard(d1));
+    }
+
+    let results = match d1.batch(queries).await {
+        Ok(results) => results,
+        Err(e) if inventory::is_stock_shortage(&e) => {
+            return Response::error("Недостаточно товара на складе", 409)
+        }
+        Err(e) if delivery::is_slot_full(&e) => {
+            return Response::error("Выбранный слот доставки уже заполнен", 409)
+        }
+        Err(e) => return Err(e),
+    };
+    let order = results
+        .get(1)
+        .and_then(|r| r.results::<NewOrder>().ok())
+        .and_then(|rows| rows.into_iter().next())
+        .ok_or("Order insert failed")?;
+
+    // Уведомления, статистика и проверка остатков — через очередь, вне запроса покупателя
+    jobs::enqueue(
+        ctx,
+        vec![
+            Job::OrderNotification { order_id: order.id },
+            Job::OrderAnalytics { order_id: order.id },
+            Job::StockCheck {
+                product_ids: items.iter().map(|item| item.id).collect(),
+            },
+        ],
+    );
+
+    Response::from_json(&serde_json::json!({
+        "success": true,
+        "id": order.id,
+        "order_number": order.order_number,
+        "total": total,
+        "delivery_fee": fee,
+        "placed_while_closed": placed_while_closed,
+        "next_open_at": store.next_open_at.map(|t| clock::format(&t)),
+    }))
+}
+
+// Отслеживание заказа покупателем: номер заказа + телефон
+pub async fn track_order(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let url = req.url()?;
+    let query_pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+
+    let param = |name: &str| {
+        query_pairs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.trim().to_string())
+            .unwrap_or_default()
+    };
+    let number = param("number").to_uppercase();
 
-        let stock_query = d1
-            .prepare("UPDATE products SET stock = stock - ? WHERE id = ? AND stock >= ?")
-            .bind(&[qty.into(), id.into(), qty.into()])?;
+    let Some(phone) = normalize_phone(&param("phone")).filter(|_| !number.is_empty()) else {
+        return Response::error("Укажите номер заказа и телефон", 400);
+    };
 
-        queries.push(stock_query);
+    let order = d1
+        .prepare("SELECT * FROM orders WHERE order_number = ?")
+        .bind(&[number.into()])?
+        .first::<Order>(None)
+        .await?;
+
+    // Одинаковый ответ, если заказа нет и если телефон не совпал — не раскрываем чужие заказы
+    let order = match order {
+        Some(order) if order.customer_phone == phone => order,
+        _ => return Response::error("Заказ не найден", 404),
+    };
+
+    let history = d1
+        .prepare(
+            "SELECT status, note, created_at FROM order_status_history WHERE order_id = ? ORDER BY id",
+        )
+        .bind(&[order.id.into()])?
+        .all()
+        .await?
+        .results::<OrderStatusChange>()?;
+
+    // Позиции с пометками сборки: замены, недовес, отсутствующие товары
+    let items = parse_items(&order.items_json);
+
+    Response::from_json(&serde_json::json!({
+        "order_number": order.order_number,
+        "status": order.status,
+        "created_at": clock::to_rfc3339(&order.created_at),
+        "total_price": order.total_price,
+        "delivery_fee": order.delivery_fee,
+        "delivery_date": order.delivery_date,
+        "items": items,
+        "history": history,
+    }))
+}
+
+// Карточка заказа для админки: позиции, история статусов и журнал изменений
+pub async fn get_order(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+
+    let order = match d1
+        .prepare("SELECT * FROM orders WHERE id = ?")
+        .bind(&[id.clone().into()])?
+        .first::<Order>(None)
+        .await
+    {
+        Ok(Some(order)) => order,
+        Ok(None) => return Response::error("Заказ не найден", 404),
+        Err(e) => return Response::error(format!("D1 Error: {}", e), 500),
+    };
+
+    let results = d1
+        .batch(vec![
+            d1.prepare(
+                "SELECT status, note, created_at FROM order_status_history WHERE order_id = ? ORDER BY id",
+            )
+            .bind(&[id.clone().into()])?,
+            d1.prepare("SELECT * FROM order_audit_log WHERE order_id = ? ORDER BY id")
+                .bind(&[id.into()])?,
+        ])
+        .await?;
+
+    let items = parse_items(&order.items_json);
+    let history = results[0].results::<OrderStatusChange>()?;
+    let audit = results[1].results::<OrderAuditEntry>()?;
+
+    let mut detail = serde_json::to_value(&order)?;
+    detail["items"] = serde_json::to_value(&items)?;
+    detail["history"] = serde_json::to_value(&history)?;
+    detail["audit"] = serde_json::to_value(&audit)?;
+
+    Response::from_json(&detail)
+}
+
+// Редактирование заказа админом: данные покупателя и позиции.
+// Остатки корректируются на разницу количеств, сумма пересчитывается
+pub async fn edit_order(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let order = match d1
+        .prepare("SELECT * FROM orders WHERE id = ?")
+        .bind(&[id.clone().into()])?
+        .first::<Order>(None)
+        .await?
+    {
+        Some(order) => order,
+        None => return Response::error("Заказ не найден", 404),
+    };
+
+    let mut changes = serde_json::Map::new();
+
+    // 1. Данные покупателя: меняем только присланные поля
+    let customer_in = &body["customer"];
+    let mut customer = serde_json::json!({
+        "name": order.customer_name,
+        "phone": order.customer_phone,
+        "address": order.address,
+        "comment": order.comment.clone().unwrap_or_default(),
+    });
+    for field in ["name", "phone", "address", "comment"] {
+        if let Some(value) = customer_in[field].as_str() {
+            customer[field] = value.into();
+        }
+    }
+    let customer = match validate_customer(&customer) {
+        Ok(customer) => customer,
+        Err(e) => return Response::error(e.message(&req), 400),
+    };
+
+    for (field, before, after) in [
+        ("name", order.customer_name.as_str(), customer.name.as_str()),
+        (
+            "phone",
+            order.customer_phone.as_str(),
+            customer.phone.as_str(),
+        ),
+        ("address", order.address.as_str(), customer.address.as_str()),
+        (
+            "comment",
+            order.comment.as_deref().unwrap_or(""),
+            customer.comment.as_str(),
+        ),
+    ] {
+        if before != after {
+            changes.insert(
+                field.to_string(),
+                serde_json::json!({ "from": before, "to": after }),
+            );
+        }
+    }
+
+    // 2. Позиции заказа
+    let old_items = parse_items(&order.items_json);
+    let mut items = old_items.clone();
+    let mut new_ids: Vec<i32> = Vec::new();
+
+    if let Some(remove) = body["remove"].as_array() {
+        let remove: Vec<i32> = remove.iter().map(|v| json_id(v) as i32).collect();
+        items.retain(|item| !remove.contains(&item.id));
+    }
+
+    // replace: [{ id, quantity, product_id? }] — новое количество и/или другой товар
+    if let Some(replace) = body["replace"].as_array() {
+        for line in replace {
+            let id = item_id(line) as i32;
+            let Some(index) = items.iter().position(|item| item.id == id) else {
+                return Response::error(format!("Позиция {} не найдена в заказе", id), 400);
+            };
+            if let Some(qty) = line["quantity"].as_f64() {
+                items[index].quantity = qty;
+            }
+            let product_id = json_id(&line["product_id"]) as i32;
+            if product_id != 0 && product_id != id {
+                match items.iter().position(|item| item.id == product_id) {
+                    // Такой товар уже есть в заказе — переносим количество в его позицию
+                    Some(existing) => {
+                        let moved = items.remove(index);
+                        let existing = if existing > index {
+                            existing - 1
+                        } else {
+                            existing
+                        };
+                        items[existing].quantity += moved.quantity;
+                    }
+                    None => {
+                        items[index].id = product_id;
+                        new_ids.push(product_id);
+                    }
+                }
+            }
+        }
+    }
+
+    // add: [{ id, quantity }] — если товар уже есть в заказе, увеличиваем количество
+    if let Some(add) = body["add"].as_array() {
+        for line in add {
+            let id = item_id(line) as i32;
+            let qty = line["quantity"].as_f64().unwrap_or(0.0);
+            match items.iter_mut().find(|item| item.id == id) {
+                Some(item) => item.quantity += qty,
+                None => {
+                    items.push(OrderItem {
+                        id,
+                        quantity: qty,
+                        ..Default::default()
+                    });
+                    new_ids.push(id);
+                }
+            }
+        }
+    }
+
+    // Отсутствующие при сборке позиции оставляем, чтобы покупатель видел, чего не было
+    items.retain(|item| item.quantity > 0.0 || item.fulfillment.as_deref() == Some("missing"));
+
+    let items_changed = serde_json::to_value(&items)? != serde_json::to_value(&old_items)?;
+    if items_changed && order.status == "cancelled" {
+        return Response::error("Нельзя изменить позиции отменённого заказа", 400);
+    }
+
+    // Разница количеств по каждому товару: > 0 — списать со склада, < 0 — вернуть
+    let mut deltas: HashMap<i32, f64> = HashMap::new();
+    for item in &items {
+        *deltas.entry(item.id).or_default() += item.quantity;
+    }
+    for item in &old_items {
+        *deltas.entry(item.id).or_default() -= item.quantity;
+    }
+    deltas.retain(|_, delta| delta.abs() > f64::EPSILON);
+
+    // Подтягиваем товары: цены и названия для новых позиций, остатки для списания
+    let product_ids: Vec<i32> = deltas
+        .keys()
+        .copied()
+        .chain(new_ids.iter().copied())
+        .collect();
+    let mut products: HashMap<i32, Product> = HashMap::new();
+    if !product_ids.is_empty() {
+        let placeholders = product_ids
+            .iter()
+            .map(|_| "?")
+            .collect::<Vec<_>>()
+            .join(",");
+        let params: Vec<wasm_bindgen::JsValue> = product_ids.iter().map(|&id| id.into()).collect();
+        let mut rows = d1
+            .prepare(format!(
+                "SELECT * FROM products WHERE id IN ({})",
+                placeholders
+            ))
+            .bind(&params)?
+            .all()
+            .await?
+            .results::<Product>()?;
+        resolve_variants(&d1, &mut rows).await?;
+        products = rows.into_iter().map(|p| (p.id, p)).collect();
+    }
+
+    // Товар с вариантами в заказ не попадает — нужна конкретная фасовка
+    let parents = with_variants(&d1, &new_ids).await?;
+    for item in items.iter_mut() {
+        if !new_ids.contains(&item.id) {
+            continue;
+        }
+        let Some(product) = products.get(&item.id) else {
+            return Response::error(format!("Товар {} не найден", item.id), 404);
+        };
+        if parents.contains(&product.id) {
+            return Response::error(format!("Выберите вариант товара «{}»", product.name), 400);
+        }
+        item.name = product.name.clone();
+        item.price = product.price;
+        item.extra = serde_json::Map::new();
+        if let Some(unit) = &product.unit {
+            item.extra.insert("unit".to_string(), unit.clone().into());
+        }
+    }
+
+    for (id, delta) in &deltas {
+        if *delta > 0.0 {
+            let stock = products.get(id).and_then(|p| p.stock).unwrap_or(0.0);
+            if stock < *delta {
+                return Response::error(
+                    format!("Недостаточно товара {} на складе (осталось {})", id, stock),
+                    409,
+                );
+            }
+        }
     }
 
-    d1.batch(queries).await?;
+    let discount = promo_discount(&d1, order.promo_code.as_deref()).await?;
+    let (fee, total) = if items_changed {
+        let goods_total = items_total(&items, discount);
+        match requote_delivery(&d1, &order, goods_total, true).await? {
+            Ok(fee) => (fee, goods_total + fee),
+            Err(e) => return Response::error(e, 422),
+        }
+    } else {
+        (order.delivery_fee, order.total_price)
+    };
+
+    if items_changed {
+        changes.insert(
+            "items".to_string(),
+            serde_json::json!({ "from": old_items, "to": items }),
+        );
+    }
+    if fee != order.delivery_fee {
+        changes.insert(
+            "delivery_fee".to_string(),
+            serde_json::json!({ "from": order.delivery_fee, "to": fee }),
+        );
+    }
+    if total != order.total_price {
+        changes.insert(
+            "total_price".to_string(),
+            serde_json::json!({ "from": order.total_price, "to": total }),
+        );
+    }
+
+    if changes.is_empty() {
+        return Response::from_json(&serde_json::json!({ "success": true, "changed": false }));
+    }
+
+    let actor = body["actor"].as_str().unwrap_or("admin").to_string();
+    let items_json = serde_json::to_string(&items)?;
+
+    let mut queries = vec![
+        d1.prepare(
+            "UPDATE orders SET customer_name = ?, customer_phone = ?, address = ?, comment = ?, items_json = ?, delivery_fee = ?, total_price = ? WHERE id = ?",
+        )
+        .bind(&[
+            customer.name.into(),
+            customer.phone.into(),
+            customer.address.into(),
+            customer.comment.into(),
+            items_json.into(),
+            fee.into(),
+            total.into(),
+            id.clone().into(),
+        ])?,
+        d1.prepare(
+            "INSERT INTO order_audit_log (order_id, action, changes_json, actor, created_at) VALUES (?, 'edit', ?, ?, datetime('now'))",
+        )
+        .bind(&[
+            id.into(),
+            serde_json::Value::Object(changes.clone()).to_string().into(),
+            actor.clone().into(),
+        ])?,
+    ];
+
+    // Списание проверяется в самом batch: если остаток успели раскупить после проверки выше,
+    // заказ не меняется
+    let product_ids: Vec<i32> = deltas.keys().copied().collect();
+    for (product_id, delta) in deltas {
+        let movement = StockMovement {
+            actor: Some(actor.clone()),
+            order_id: Some(order.id),
+            note: Some("Изменение заказа".to_string()),
+            require_stock: delta > 0.0,
+            ..StockMovement::new(product_id, -delta, MovementReason::for_order(-delta))
+        };
+        queries.extend(movement.statements(&d1)?);
+        if movement.require_stock {
+            queries.push(inventory::stock_guard(&d1));
+        }
+    }
+
+    match d1.batch(queries).await {
+        Ok(_) => {}
+        Err(e) if inventory::is_stock_shortage(&e) => {
+            return Response::error("Недостаточно товара на складе", 409)
+        }
+        Err(e) => return Err(e),
+    }
+    if !product_ids.is_empty() {
+        jobs::enqueue(&ctx, vec![Job::StockCheck { product_ids }]);
+    }
+
+    Response::from_json(&serde_json::json!({
+        "success": true,
+        "changed": true,
+        "delivery_fee": fee,
+        "total_price": total,
+        "changes": changes,
+    }))
+}
+
+// Сборка заказа: замена товара, частичная отгрузка (фактический вес) или отсутствие.
+// body: { item_id, action: "substitute" | "partial" | "missing", product_id?, quantity?, restock?, note?, actor? }
+pub async fn fulfill_order_item(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let order = match d1
+        .prepare("SELECT * FROM orders WHERE id = ?")
+        .bind(&[id.clone().into()])?
+        .first::<Order>(None)
+        .await?
+    {
+        Some(order) => order,
+        None => return Response::error("Заказ не найден", 404),
+    };
+
+    if matches!(order.status.as_str(), "cancelled" | "delivered") {
+        return Response::error("Заказ уже закрыт", 400);
+    }
+
+    let action = body["action"].as_str().unwrap_or("").trim().to_lowercase();
+    let line_id = json_id(&body["item_id"]) as i32;
+    let note = body["note"]
+        .as_str()
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty());
+
+    let mut items = parse_items(&order.items_json);
+    let Some(index) = items.iter().position(|item| item.id == line_id) else {
+        return Response::error(format!("Позиция {} не найдена в заказе", line_id), 400);
+    };
+    let before = items[index].clone();
+
+    // Возвращать ли на склад то, что не уехало к покупателю. По умолчанию — только при недовесе:
+    // при замене и отсутствии товара его, как правило, просто нет на полке
+    let restock = body["restock"].as_bool().unwrap_or(action == "partial");
+
+    // (товар, изменение остатка)
+    let mut stock_changes: Vec<(i32, f64)> = Vec::new();
+
+    let item = &mut items[index];
+    match action.as_str() {
+        "partial" => {
+            let Some(actual) = body["quantity"].as_f64().filter(|q| *q > 0.0) else {
+                return Response::error("Укажите фактическое количество", 400);
+            };
+            item.ordered_quantity = Some(item.ordered_quantity.unwrap_or(item.quantity));
+            item.fulfillment = Some("partial".to_string());
+            let diff = actual - item.quantity;
+            if diff > 0.0 || restock {
+                stock_changes.push((item.id, -diff));
+            }
+            item.quantity = actual;
+        }
+        "missing" => {
+            item.ordered_quantity = Some(item.ordered_quantity.unwrap_or(item.quantity));
+            item.fulfillment = Some("missing".to_string());
+            if restock {
+                stock_changes.push((item.id, item.quantity));
+            }
+            item.quantity = 0.0;
+        }
+        "substitute" => {
+            let product_id = json_id(&body["product_id"]) as i32;
+            if product_id == 0 || product_id == item.id {
+                return Response::error("Укажите товар для замены", 400);
+            }
+
+            let Some(product) = d1
+                .prepare("SELECT * FROM products WHERE id = ?")
+                .bind(&[product_id.into()])?
+                .first::<Product>(None)
+                .await?
+            else {
+                return Response::error("Товар для замены не найден", 404);
+            };
+            if with_variants(&d1, &[product.id])
+                .await?
+                .contains(&product.id)
+            {
+                return Response::error(format!("Выберите вариант товара «{}»", product.name), 400);
+            }
+            let mut resolved = [product];
+            resolve_variants(&d1, &mut resolved).await?;
+            let [product] = resolved;
+
+            let qty = body["quantity"]
+                .as_f64()
+                .filter(|q| *q > 0.0)
+                .unwrap_or(item.quantity);
+            let stock = product.stock.unwrap_or(0.0);
+            if stock < qty {
+                return Response::error(
+                    format!("Недостаточно товара на складе (осталось {})", stock),
+                    409,
+                );
+            }
+
+            if restock {
+                stock_changes.push((item.id, item.quantity));
+            }
+            stock_changes.push((product.id, -qty));
+
+            // Если позицию уже меняли — помним самый первый товар
+            let replaced = item.replaced.clone().unwrap_or(ReplacedItem {
+                id: item.id,
+                name: item.name.clone(),
+                price: item.price,
+                quantity: item.ordered_quantity.unwrap_or(item.quantity),
+            });
+
+            *item = OrderItem {
+                id: product.id,
+                name: product.name,
+                price: product.price,
+                quantity: qty,
+                fulfillment: Some("substituted".to_string()),
+                replaced: Some(replaced),
+                ..Default::default()
+            };
+            if let Some(unit) = product.unit {
+                item.extra.insert("unit".to_string(), unit.into());
+            }
+            if let Some(image) = product.image {
+                item.extra.insert("image".to_string(), image.into());
+            }
+        }
+        _ => return Response::error("Неизвестное действие", 400),
+    }
+
+    // Замена на товар, который уже есть в заказе, — количество переносим в его позицию
+    let mut index = index;
+    let product_id = items[index].id;
+    if let Some(existing) = items
+        .iter()
+        .enumerate()
+        .position(|(i, other)| i != index && other.id == product_id)
+    {
+        let moved = items.remove(index);
+        index = if existing > index {
+            existing - 1
+        } else {
+            existing
+        };
+        items[index].quantity += moved.quantity;
+    }
+    items[index].fulfillment_note = note;
+    let after = items[index].clone();
+
+    let discount = promo_discount(&d1, order.promo_code.as_deref()).await?;
+    let goods_total = items_total(&items, discount);
+    let fee = requote_delivery(&d1, &order, goods_total, false)
+        .await?
+        .unwrap_or(order.delivery_fee);
+    let total = goods_total + fee;
+
+    let changes = serde_json::json!({
+        "action": action,
+        "item": { "from": before, "to": after },
+        "delivery_fee": { "from": order.delivery_fee, "to": fee },
+        "total_price": { "from": order.total_price, "to": total },
+        "restock": restock,
+    });
+    let actor = body["actor"].as_str().unwrap_or("admin").to_string();
+
+    let mut queries = vec![
+        d1.prepare("UPDATE orders SET items_json = ?, delivery_fee = ?, total_price = ? WHERE id = ?")
+            .bind(&[
+                serde_json::to_string(&items)?.into(),
+                fee.into(),
+                total.into(),
+                id.clone().into(),
+            ])?,
+        d1.prepare(
+            "INSERT INTO order_audit_log (order_id, action, changes_json, actor, created_at) VALUES (?, 'fulfillment', ?, ?, datetime('now'))",
+        )
+        .bind(&[id.into(), changes.to_string().into(), actor.clone().into()])?,
+    ];
+
+    let product_ids: Vec<i32> = stock_changes.iter().map(|(id, _)| *id).collect();
+    for (product_id, delta) in stock_changes {
+        let movement = StockMovement {
+            actor: Some(actor.clone()),
+            order_id: Some(order.id),
+            note: Some("Сборка заказа".to_string()),
+            require_stock: delta < 0.0,
+            ..StockMovement::new(product_id, delta, MovementReason::for_order(delta))
+        };
+        queries.extend(movement.statements(&d1)?);
+        if movement.require_stock {
+            queries.push(inventory::stock_guard(&d1));
+        }
+    }
+
+    // Довес сверх заказанного и замена списываются, только если товар есть на складе
+    match d1.batch(queries).await {
+        Ok(_) => {}
+        Err(e) if inventory::is_stock_shortage(&e) => {
+            return Response::error("Недостаточно товара на складе", 409)
+        }
+        Err(e) => return Err(e),
+    }
+    if !product_ids.is_empty() {
+        jobs::enqueue(&ctx, vec![Job::StockCheck { product_ids }]);
+    }
 
-    Response::from_json(&serde_json::json!({ "success": true }))
+    Response::from_json(&serde_json::json!({
+        "success": true,
+        "item": after,
+        "delivery_fee": fee,
+        "total_price": total,
+    }))
 }
@@ -1,48 +1,86 @@
+use crate::handlers::attributes;
+use crate::handlers::inventory::{self, MovementReason};
+use crate::jobs::{self, Job};
 use crate::models::Product;
+use crate::patch::{self, FieldKind};
+use crate::storefront;
+use crate::validation;
+use crate::versioning;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 use worker::*;
 
-// 1. Получение списка
-pub async fn list_products(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let d1 = ctx.env.d1("akniet_db")?;
-    let url = req.url()?;
-    let query_pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
-
-    // Проверяем, пришел ли флаг admin=true
-    let is_admin = query_pairs.iter().any(|(k, v)| k == "admin" && v == "true");
-
-    // Если админ — показываем всё, если покупатель — только stock > 0
-    let mut sql = if is_admin {
-        "SELECT * FROM products WHERE 1=1".to_string()
-    } else {
-        "SELECT * FROM products WHERE stock > 0".to_string()
-    };
-
+// Фильтры списка товаров (" AND ..." и параметры); их же понимает выгрузка каталога
+pub fn product_filters(query_pairs: &[(String, String)]) -> (String, Vec<wasm_bindgen::JsValue>) {
+    let mut sql = String::new();
     let mut params: Vec<wasm_bindgen::JsValue> = Vec::new();
-    let mut has_filters = false;
+    // attr[код]=значение; повторённый код — любое из значений
+    let mut attribute_values: Vec<(&str, Vec<&str>)> = Vec::new();
+
+    for (key, value) in query_pairs {
+        if let Some(code) = attributes::filter_code(key) {
+            if !value.trim().is_empty() {
+                match attribute_values.iter_mut().find(|(c, _)| *c == code) {
+                    Some((_, values)) => values.push(value),
+                    None => attribute_values.push((code, vec![value])),
+                }
+            }
+            continue;
+        }
 
-    // Обработка фильтров
-    for (key, value) in &query_pairs {
         match key.as_str() {
             "categoryId" | "category_id" => {
                 if let Ok(id) = value.parse::<i32>() {
                     sql.push_str(" AND category_id = ?");
                     params.push(id.into());
-                    has_filters = true;
                 }
             }
+            // Поиск по названию, описанию, артикулу и штрихкоду (сканером в поле поиска)
             "q" if !value.is_empty() => {
-                sql.push_str(" AND (name LIKE ? OR name_kk LIKE ? OR description LIKE ?)");
-                let pattern = format!("%{}%", value);
-                params.push(pattern.clone().into());
-                params.push(pattern.clone().into());
-                params.push(pattern.into());
-                has_filters = true;
+                sql.push_str(
+                    " AND (name LIKE ? OR name_kk LIKE ? OR description LIKE ? OR sku LIKE ?
+                      OR id IN (SELECT product_id FROM product_barcodes WHERE barcode LIKE ?))",
+                );
+                let pattern = format!("%{}%", value.trim());
+                for _ in 0..5 {
+                    params.push(pattern.clone().into());
+                }
             }
             _ => {}
         }
     }
 
+    for (code, values) in attribute_values {
+        let (attribute_sql, attribute_params) = attributes::attribute_filter(code, &values);
+        sql.push_str(&attribute_sql);
+        params.extend(attribute_params);
+    }
+
+    (sql, params)
+}
+
+// 1. Получение списка
+pub async fn list_products(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let url = req.url()?;
+    let query_pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+
+    // Проверяем, пришел ли флаг admin=true
+    let is_admin = query_pairs.iter().any(|(k, v)| k == "admin" && v == "true");
+
+    // Если админ — показываем всё (включая варианты), если покупатель — только товары
+    // верхнего уровня в наличии и не скрытые
+    let mut sql = if is_admin {
+        "SELECT * FROM products WHERE 1=1".to_string()
+    } else {
+        format!("SELECT * FROM products WHERE {}", STOREFRONT_VISIBLE)
+    };
+
+    let (filter_sql, params) = product_filters(&query_pairs);
+    let has_filters = !filter_sql.is_empty();
+    sql.push_str(&filter_sql);
+
     // Если пользователь ничего не ищет и не выбрал категорию — перемешиваем витрину.
 
     if has_filters {
@@ -56,12 +94,14 @@ pub async fn list_products(req: Request, ctx: RouteContext<()>) -> Result<Respon
 
     let statement = d1.prepare(&sql).bind(&params)?;
     let result = statement.all().await?;
+    let mut products = result.results::<Product>()?;
+    attach_barcodes(&d1, &mut products).await?;
 
-    Response::from_json(&result.results::<Product>()?)
+    Response::from_json(&products)
 }
 
 // 3. Создание товара
-pub async fn create_product(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn create_product(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let form = req.form_data().await?; // Используем встроенный метод
     let bucket = ctx.env.bucket("akniet_bucket")?;
     let d1 = ctx.env.d1("akniet_db")?;
@@ -73,6 +113,7 @@ pub async fn create_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
     let mut old_price: Option<f64> = None;
     let mut unit = String::new();
     let mut stock: i32 = 0;
+    let mut low_stock_threshold: Option<f64> = None;
     let mut description = String::new();
     let mut description_kk = String::new();
     let mut image_urls: Vec<String> = Vec::new();
@@ -102,6 +143,9 @@ pub async fn create_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
     if let Some(FormEntry::Field(val)) = form.get("stock") {
         stock = val.parse().unwrap_or(0);
     }
+    if let Some(FormEntry::Field(val)) = form.get("low_stock_threshold") {
+        low_stock_threshold = val.parse().ok();
+    }
     if let Some(FormEntry::Field(val)) = form.get("description") {
         description = val;
     }
@@ -109,42 +153,110 @@ pub async fn create_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
         description_kk = val;
     }
 
+    // Вариант товара: parent_id родителя и подпись фасовки ("1 л")
+    let parent_id = match form.get("parent_id") {
+        Some(FormEntry::Field(s)) => s.trim().parse::<i32>().ok().filter(|id| *id > 0),
+        _ => None,
+    };
+    let label = |field: &str| match form.get(field) {
+        Some(FormEntry::Field(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        _ => None,
+    };
+    let (variant_label, variant_label_kk) = (label("variant_label"), label("variant_label_kk"));
+    if let Some(parent_id) = parent_id {
+        if let Err(e) = check_parent(&d1, None, parent_id).await? {
+            return Response::error(e, 400);
+        }
+        // Общее название у родителя; своё у варианта — только если прислали
+        if name.trim().is_empty() {
+            if let Some(parent) = find_product(&d1, parent_id).await? {
+                name = parent.name;
+            }
+        }
+    }
+
+    // Артикул и штрихкоды проверяем до загрузки картинок
+    let sku = match form.get("sku") {
+        Some(FormEntry::Field(s)) if !s.trim().is_empty() => match validation::normalize_sku(&s) {
+            Some(sku) => Some(sku),
+            None => return Response::error("Неверный артикул", 400),
+        },
+        _ => None,
+    };
+    let barcodes = match form.get("barcodes") {
+        Some(FormEntry::Field(s)) => match validation::parse_barcodes([s.as_str()]) {
+            Ok(barcodes) => barcodes,
+            Err(e) => return Response::error(e, 400),
+        },
+        _ => Vec::new(),
+    };
+    if let Some(conflict) = identifier_conflict(&d1, None, sku.as_deref(), &barcodes).await? {
+        return Response::error(conflict, 409);
+    }
+
     // Обработка файлов
     let files = form.get_all("imageFiles").unwrap_or_default();
+    let mut uploads = Vec::new();
     for entry in files {
         if let FormEntry::File(file) = entry {
             let file_name = format!("prod-{}.jpg", Uuid::new_v4());
             let bytes = file.bytes().await?;
             bucket.put(&file_name, bytes).execute().await?;
+            uploads.push(Job::ImageUploaded {
+                key: file_name.clone(),
+            });
             image_urls.push(format!("https://img.tabys-go.ru/{}", file_name));
         }
     }
 
     let images_json = serde_json::to_string(&image_urls).unwrap_or_default();
 
-    let query = "INSERT INTO products (name, name_kk, category_id, price, old_price, unit, image, description, description_kk, stock) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
-    d1.prepare(query)
-        .bind(&[
-            name.into(),
-            name_kk.into(),
-            category_id.into(),
-            price.into(),
-            old_price.into(),
-            unit.into(),
-            images_json.into(),
-            description.into(),
-            description_kk.into(),
-            stock.into(),
-        ])?
-        .run()
-        .await?;
+    let query = "INSERT INTO products (name, name_kk, category_id, price, old_price, unit, image, description, description_kk, stock, low_stock_threshold, sku, parent_id, variant_label, variant_label_kk, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'), datetime('now'))";
+    let actor = match form.get("actor") {
+        Some(FormEntry::Field(s)) if !s.trim().is_empty() => s.trim().to_string(),
+        _ => "admin".to_string(),
+    };
 
+    // Товар и начальный остаток в журнале движения — одним batch
+    let insert = d1.prepare(query).bind(&[
+        name.into(),
+        name_kk.into(),
+        category_id.into(),
+        price.into(),
+        old_price.into(),
+        unit.into(),
+        images_json.into(),
+        description.into(),
+        description_kk.into(),
+        stock.into(),
+        low_stock_threshold.into(),
+        sku.into(),
+        parent_id.into(),
+        variant_label.into(),
+        variant_label_kk.into(),
+    ])?;
+    // Штрихкоды — после записи в журнал: initial_stock опирается на last_insert_rowid()
+    let mut statements = vec![
+        insert,
+        inventory::initial_stock(&d1, stock as f64, Some(&actor))?,
+    ];
+    if !barcodes.is_empty() {
+        statements.extend(barcode_statements(&d1, None, &barcodes)?);
+    }
+    if let Err(e) = d1.batch(statements).await {
+        if is_unique_violation(&e) {
+            return Response::error("Артикул или штрихкод уже есть у другого товара", 409);
+        }
+        return Err(e);
+    }
+
+    jobs::enqueue(&ctx, uploads);
     Response::ok("Success")
 }
 
 // удаление товара
 
-pub async fn delete_product(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn delete_product(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
     let bucket = ctx.env.bucket("akniet_bucket")?;
 
@@ -161,15 +273,23 @@ pub async fn delete_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
     if id == 0 {
         return Response::error("ID товара не передан или равен 0", 400);
     }
+    if with_variants(&d1, &[id]).await?.contains(&id) {
+        return Response::error("У товара есть варианты — сначала удалите их", 409);
+    }
 
     // Удаляем из базы
-    d1.prepare("DELETE FROM products WHERE id = ?")
-        .bind(&[id.into()])?
-        .run()
-        .await?;
+    d1.batch(vec![
+        d1.prepare("DELETE FROM products WHERE id = ?")
+            .bind(&[id.into()])?,
+        d1.prepare("DELETE FROM product_barcodes WHERE product_id = ?")
+            .bind(&[id.into()])?,
+        d1.prepare("DELETE FROM product_attribute_values WHERE product_id = ?")
+            .bind(&[id.into()])?,
+    ])
+    .await?;
 
     if !image_url.is_empty() && image_url.contains('/') {
-        if let Some(file_name) = image_url.split('/').last() {
+        if let Some(file_name) = image_url.split('/').next_back() {
             let _ = bucket.delete(file_name).await;
         }
     }
@@ -178,29 +298,379 @@ pub async fn delete_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
 }
 
 // поиск одного товара
-pub async fn get_product(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+// Вместе с товаром отдаются его варианты (фасовки). Админке (?admin=true) — все варианты
+// и поля как в базе; покупателю — только не скрытые, а вариант — с данными родителя
+pub async fn get_product(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
     let d1 = ctx.env.d1("akniet_db")?;
+    let is_admin = req
+        .url()?
+        .query_pairs()
+        .any(|(k, v)| k == "admin" && v == "true");
 
     let statement = d1
         .prepare("SELECT * FROM products WHERE id = ?")
         .bind(&[id.into()])?;
 
     match statement.first::<Product>(None).await {
-        Ok(Some(product)) => Response::from_json(&product),
+        Ok(Some(product)) => {
+            let mut products = [product];
+            attach_barcodes(&d1, &mut products).await?;
+            attributes::attach_attributes(&d1, &mut products).await?;
+            attach_variants(&d1, &mut products, is_admin).await?;
+            if !is_admin {
+                resolve_variants(&d1, &mut products).await?;
+            }
+            let [product] = products;
+            // Скрытый товар (или вариант скрытого) покупателю не показываем
+            if !is_admin && product.is_hidden != 0 {
+                return Response::error("Товар не найден", 404);
+            }
+            versioning::with_etag(Response::from_json(&product)?, product.version)
+        }
         Ok(None) => Response::error("Товар не найден", 404),
         Err(e) => Response::error(format!("Ошибка базы данных: {}", e), 500),
     }
 }
 
+async fn find_product(d1: &D1Database, id: i32) -> Result<Option<Product>> {
+    d1.prepare("SELECT * FROM products WHERE id = ?")
+        .bind(&[id.into()])?
+        .first::<Product>(None)
+        .await
+}
+
+#[derive(Deserialize)]
+struct ProductBarcode {
+    product_id: i32,
+    barcode: String,
+}
+
+// Штрихкоды товаров: product_id -> список в порядке добавления
+pub async fn load_barcodes(
+    d1: &D1Database,
+    product_ids: &[i32],
+) -> Result<HashMap<i32, Vec<String>>> {
+    let mut barcodes: HashMap<i32, Vec<String>> = HashMap::new();
+    if product_ids.is_empty() {
+        return Ok(barcodes);
+    }
+    // Список id одним JSON-параметром: у D1 ограничение в 100 параметров
+    let rows = d1
+        .prepare(
+            "SELECT product_id, barcode FROM product_barcodes
+             WHERE product_id IN (SELECT value FROM json_each(?)) ORDER BY rowid",
+        )
+        .bind(&[serde_json::to_string(product_ids)?.into()])?
+        .all()
+        .await?
+        .results::<ProductBarcode>()?;
+    for row in rows {
+        barcodes
+            .entry(row.product_id)
+            .or_default()
+            .push(row.barcode);
+    }
+    Ok(barcodes)
+}
+
+pub async fn attach_barcodes(d1: &D1Database, products: &mut [Product]) -> Result<()> {
+    let ids: Vec<i32> = products.iter().map(|p| p.id).collect();
+    let mut barcodes = load_barcodes(d1, &ids).await?;
+    for product in products {
+        product.barcodes = Some(barcodes.remove(&product.id).unwrap_or_default());
+    }
+    Ok(())
+}
+
+// Артикул или штрихкод уже есть у другого товара -> текст ошибки
+pub async fn identifier_conflict(
+    d1: &D1Database,
+    product_id: Option<i32>,
+    sku: Option<&str>,
+    barcodes: &[String],
+) -> Result<Option<String>> {
+    let product_id = product_id.unwrap_or(0);
+    if let Some(sku) = sku {
+        let owner = d1
+            .prepare("SELECT id FROM products WHERE sku = ? COLLATE NOCASE AND id <> ?")
+            .bind(&[sku.into(), product_id.into()])?
+            .first::<serde_json::Value>(None)
+            .await?;
+        if let Some(owner) = owner {
+            return Ok(Some(format!(
+                "Артикул {} уже есть у товара {}",
+                sku, owner["id"]
+            )));
+        }
+    }
+    if !barcodes.is_empty() {
+        let owner = d1
+            .prepare(
+                "SELECT product_id, barcode FROM product_barcodes
+                 WHERE barcode IN (SELECT value FROM json_each(?)) AND product_id <> ?",
+            )
+            .bind(&[serde_json::to_string(barcodes)?.into(), product_id.into()])?
+            .first::<ProductBarcode>(None)
+            .await?;
+        if let Some(owner) = owner {
+            return Ok(Some(format!(
+                "Штрихкод {} уже есть у товара {}",
+                owner.barcode, owner.product_id
+            )));
+        }
+    }
+    Ok(None)
+}
+
+// Заменяет штрихкоды товара. Без product_id — для товара, добавленного
+// раньше в том же batch (batch выполняется одной транзакцией)
+pub fn barcode_statements(
+    d1: &D1Database,
+    product_id: Option<i32>,
+    barcodes: &[String],
+) -> Result<Vec<D1PreparedStatement>> {
+    let list = serde_json::to_string(barcodes)?;
+    Ok(match product_id {
+        Some(id) => vec![
+            d1.prepare("DELETE FROM product_barcodes WHERE product_id = ?")
+                .bind(&[id.into()])?,
+            d1.prepare(
+                "INSERT INTO product_barcodes (barcode, product_id, created_at)
+                 SELECT value, ?, datetime('now') FROM json_each(?)",
+            )
+            .bind(&[id.into(), list.into()])?,
+        ],
+        None => vec![d1
+            .prepare(
+                "INSERT INTO product_barcodes (barcode, product_id, created_at)
+                 SELECT value, (SELECT MAX(id) FROM products), datetime('now') FROM json_each(?)",
+            )
+            .bind(&[list.into()])?],
+    })
+}
+
+fn is_unique_violation(e: &Error) -> bool {
+    e.to_string().contains("UNIQUE constraint failed")
+}
+
+// Чей уникальный ключ занят: штрихкод или артикул
+fn unique_violation_message(e: &Error) -> &'static str {
+    if e.to_string().contains("product_barcodes") {
+        "Штрихкод уже есть у другого товара"
+    } else {
+        "Артикул уже есть у другого товара"
+    }
+}
+
+// Что видит покупатель: не скрытые товары верхнего уровня, у которых есть остаток
+// у самого товара или у одного из его вариантов
+pub const STOREFRONT_VISIBLE: &str = "is_hidden = 0 AND parent_id IS NULL AND (stock > 0 OR EXISTS (SELECT 1 FROM products v WHERE v.parent_id = products.id AND v.stock > 0 AND v.is_hidden = 0))";
+
+// Варианты товаров верхнего уровня (дешёвые первыми). Покупателю — только не скрытые
+pub async fn attach_variants(
+    d1: &D1Database,
+    products: &mut [Product],
+    include_hidden: bool,
+) -> Result<()> {
+    let ids: Vec<i32> = products
+        .iter()
+        .filter(|p| p.parent_id.is_none())
+        .map(|p| p.id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let hidden_filter = if include_hidden {
+        ""
+    } else {
+        " AND is_hidden = 0"
+    };
+    let mut variants = d1
+        .prepare(format!(
+            "SELECT * FROM products WHERE parent_id IN (SELECT value FROM json_each(?)){} ORDER BY price, id",
+            hidden_filter
+        ))
+        .bind(&[serde_json::to_string(&ids)?.into()])?
+        .all()
+        .await?
+        .results::<Product>()?;
+    attach_barcodes(d1, &mut variants).await?;
+    attributes::attach_attributes(d1, &mut variants).await?;
+
+    let mut by_parent: HashMap<i32, Vec<Product>> = HashMap::new();
+    for variant in variants {
+        by_parent
+            .entry(variant.parent_id.unwrap_or_default())
+            .or_default()
+            .push(variant);
+    }
+    for product in products.iter_mut().filter(|p| p.parent_id.is_none()) {
+        product.variants = Some(by_parent.remove(&product.id).unwrap_or_default());
+    }
+    Ok(())
+}
+
+// Вариант показываем как "Молоко 1 л": название, описание, картинки и категория
+// берутся у родителя, если у варианта они не заданы. Вариант скрытого товара тоже скрыт
+pub async fn resolve_variants(d1: &D1Database, products: &mut [Product]) -> Result<()> {
+    let parent_ids: Vec<i32> = products.iter().filter_map(|p| p.parent_id).collect();
+    if parent_ids.is_empty() {
+        return Ok(());
+    }
+    let parents: HashMap<i32, Product> = d1
+        .prepare("SELECT * FROM products WHERE id IN (SELECT value FROM json_each(?))")
+        .bind(&[serde_json::to_string(&parent_ids)?.into()])?
+        .all()
+        .await?
+        .results::<Product>()?
+        .into_iter()
+        .map(|p| (p.id, p))
+        .collect();
+
+    let non_empty = |value: &Option<String>| value.as_deref().is_some_and(|v| !v.trim().is_empty());
+    for product in products.iter_mut() {
+        let Some(parent) = product.parent_id.and_then(|id| parents.get(&id)) else {
+            continue;
+        };
+        if let Some(label) = product.variant_label.as_deref().filter(|l| !l.is_empty()) {
+            let label_kk = product
+                .variant_label_kk
+                .as_deref()
+                .filter(|l| !l.is_empty())
+                .unwrap_or(label);
+            product.name = format!("{} {}", parent.name, label);
+            product.name_kk = Some(format!(
+                "{} {}",
+                parent.name_kk.as_deref().unwrap_or(&parent.name),
+                label_kk
+            ));
+        }
+        if storefront::image_urls(product).is_empty() {
+            product.image = parent.image.clone();
+        }
+        if !non_empty(&product.description) {
+            product.description = parent.description.clone();
+        }
+        if !non_empty(&product.description_kk) {
+            product.description_kk = parent.description_kk.clone();
+        }
+        if product.category_id.is_none() {
+            product.category_id = parent.category_id;
+        }
+        product.is_hidden = product.is_hidden.max(parent.is_hidden);
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct VariantParent {
+    parent_id: i32,
+}
+
+// Какие из товаров — родители с вариантами: заказать можно только конкретный вариант
+pub async fn with_variants(d1: &D1Database, ids: &[i32]) -> Result<HashSet<i32>> {
+    if ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+    Ok(d1
+        .prepare(
+            "SELECT DISTINCT parent_id FROM products WHERE parent_id IN (SELECT value FROM json_each(?))",
+        )
+        .bind(&[serde_json::to_string(ids)?.into()])?
+        .all()
+        .await?
+        .results::<VariantParent>()?
+        .into_iter()
+        .map(|row| row.parent_id)
+        .collect())
+}
+
+// Родитель для нового варианта: существует и сам не является вариантом
+async fn check_parent(
+    d1: &D1Database,
+    product_id: Option<i32>,
+    parent_id: i32,
+) -> Result<std::result::Result<(), String>> {
+    if Some(parent_id) == product_id {
+        return Ok(Err("Товар не может быть вариантом самого себя".to_string()));
+    }
+    let Some(parent) = find_product(d1, parent_id).await? else {
+        return Ok(Err(format!("Товар {} не найден", parent_id)));
+    };
+    if parent.parent_id.is_some() {
+        return Ok(Err("Вариант не может иметь своих вариантов".to_string()));
+    }
+    if let Some(id) = product_id {
+        if with_variants(d1, &[id]).await?.contains(&id) {
+            return Ok(Err(
+                "У товара есть свои варианты — он не может стать вариантом".to_string(),
+            ));
+        }
+    }
+    Ok(Ok(()))
+}
+
+// Товар по штрихкоду: GET /api/products/by-barcode/:code. Приложению сборщика (?admin=true)
+// находятся и скрытые товары — это складской поиск; покупателю скрытые не показываем
+pub async fn get_by_barcode(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let code = ctx.param("code").map(|s| s.to_string()).unwrap_or_default();
+    let is_admin = req
+        .url()?
+        .query_pairs()
+        .any(|(k, v)| k == "admin" && v == "true");
+    let Some(barcode) = validation::normalize_barcode(&code) else {
+        return Response::error("Неверный штрихкод", 400);
+    };
+    let d1 = ctx.env.d1("akniet_db")?;
+
+    let product = d1
+        .prepare(
+            "SELECT products.* FROM products
+             JOIN product_barcodes ON product_barcodes.product_id = products.id
+             WHERE product_barcodes.barcode = ?",
+        )
+        .bind(&[barcode.into()])?
+        .first::<Product>(None)
+        .await?;
+    let Some(product) = product else {
+        return Response::error("Товар не найден", 404);
+    };
+
+    let mut products = [product];
+    attach_barcodes(&d1, &mut products).await?;
+    resolve_variants(&d1, &mut products).await?;
+    let [product] = products;
+    if !is_admin && product.is_hidden != 0 {
+        return Response::error("Товар не найден", 404);
+    }
+    versioning::with_etag(Response::from_json(&product)?, product.version)
+}
+
 // изменение продукта
 
-pub async fn update_product(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn update_product(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
     let form = req.form_data().await?;
     let d1 = ctx.env.d1("akniet_db")?;
     let bucket = ctx.env.bucket("akniet_bucket")?;
 
+    // Версия, с которой админ открыл карточку: If-Match или поле version
+    let version_field = match form.get("version") {
+        Some(FormEntry::Field(s)) => Some(serde_json::Value::String(s)),
+        _ => None,
+    };
+    let Some(version) = versioning::expected_version(&req, version_field.as_ref()) else {
+        return versioning::version_required();
+    };
+    let product_id = id.parse::<i32>().unwrap_or(0);
+    let Some(current) = find_product(&d1, product_id).await? else {
+        return Response::error("Товар не найден", 404);
+    };
+    if current.version != version {
+        return versioning::conflict(&current, current.version);
+    }
+
     let name = match form.get("name") {
         Some(FormEntry::Field(s)) if s != "undefined" => s,
         _ => String::new(),
@@ -237,9 +707,50 @@ pub async fn update_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
         _ => String::new(),
     };
 
-    let stock = match form.get("stock") {
-        Some(FormEntry::Field(s)) if s != "undefined" => s.parse::<f64>().unwrap_or(0.0),
-        _ => 0.0,
+    // Остаток меняется через приёмку/списание/инвентаризацию. Здесь — только если
+    // вместе со stock пришёл expected_stock (остаток, который видел администратор)
+    let field_f64 = |name: &str| match form.get(name) {
+        Some(FormEntry::Field(s)) if s != "undefined" && !s.trim().is_empty() => {
+            Some(s.trim().parse::<f64>().map_err(|_| name.to_string()))
+        }
+        _ => None,
+    };
+    let stock_change = match (field_f64("stock"), field_f64("expected_stock")) {
+        (None, None) => None,
+        (Some(Ok(stock)), Some(Ok(expected))) => Some((stock, expected)),
+        (Some(Err(name)), _) | (_, Some(Err(name))) => {
+            return Response::error(format!("Неверное значение {}", name), 400);
+        }
+        (Some(Ok(_)), None) => {
+            return Response::error(
+                "Укажите expected_stock — остаток, который видели в карточке товара",
+                428,
+            );
+        }
+        (None, Some(Ok(_))) => {
+            return Response::error("Укажите stock вместе с expected_stock", 400)
+        }
+    };
+    if let Some((_, expected)) = stock_change {
+        let stock = current.stock.unwrap_or(0.0);
+        if stock != expected {
+            return Ok(Response::from_json(&serde_json::json!({
+                "error": "Остаток уже изменился, обновите карточку товара",
+                "stock": stock,
+            }))?
+            .with_status(409));
+        }
+    }
+
+    let actor = match form.get("actor") {
+        Some(FormEntry::Field(s)) if !s.trim().is_empty() => s.trim().to_string(),
+        _ => "admin".to_string(),
+    };
+
+    // Порог меняем, только если поле есть в форме; пустое значение — сбросить
+    let low_stock_threshold = match form.get("low_stock_threshold") {
+        Some(FormEntry::Field(s)) if s != "undefined" => Some(s.parse::<f64>().ok()),
+        _ => None,
     };
 
     let description = match form.get("description") {
@@ -251,6 +762,40 @@ pub async fn update_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
         _ => String::new(),
     };
 
+    // Артикул и штрихкоды меняем, только если поля есть в форме; пустое значение — очистить
+    let sku = match form.get("sku") {
+        Some(FormEntry::Field(s)) if s != "undefined" => {
+            if s.trim().is_empty() {
+                Some(None)
+            } else {
+                match validation::normalize_sku(&s) {
+                    Some(sku) => Some(Some(sku)),
+                    None => return Response::error("Неверный артикул", 400),
+                }
+            }
+        }
+        _ => None,
+    };
+    let barcodes = match form.get("barcodes") {
+        Some(FormEntry::Field(s)) if s != "undefined" => {
+            match validation::parse_barcodes([s.as_str()]) {
+                Ok(barcodes) => Some(barcodes),
+                Err(e) => return Response::error(e, 400),
+            }
+        }
+        _ => None,
+    };
+    if let Some(conflict) = identifier_conflict(
+        &d1,
+        Some(product_id),
+        sku.clone().flatten().as_deref(),
+        barcodes.as_deref().unwrap_or_default(),
+    )
+    .await?
+    {
+        return Response::error(conflict, 409);
+    }
+
     let mut final_images: Vec<String> = match form.get("remainingImages") {
         Some(FormEntry::Field(s)) if s != "undefined" && !s.is_empty() => {
             serde_json::from_str(&s).unwrap_or_default()
@@ -258,6 +803,9 @@ pub async fn update_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
         _ => Vec::new(),
     };
 
+    // Картинки загружаются после проверки версии; если сохранить товар не удалось,
+    // загруженные файлы удаляются
+    let mut uploaded = Vec::new();
     if let Some(entries) = form.get_all("imageFiles") {
         for entry in entries {
             if let FormEntry::File(file) = entry {
@@ -265,15 +813,18 @@ pub async fn update_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
                 let bytes = file.bytes().await?;
                 bucket.put(&file_name, bytes).execute().await?;
                 final_images.push(format!("https://img.tabys-go.ru/{}", file_name));
+                uploaded.push(file_name);
             }
         }
     }
     let images_json = serde_json::to_string(&final_images).unwrap_or_default();
 
-    let query = "UPDATE products SET name=?1, name_kk=?2, category_id=?3, price=?4, old_price=?5, unit=?6, image=?7, description=?8, description_kk=?9, stock=?10 WHERE id=?11";
+    let query = "UPDATE products SET name=?1, name_kk=?2, category_id=?3, price=?4, old_price=?5, unit=?6, image=?7, description=?8, description_kk=?9, low_stock_threshold=CASE WHEN ?11 THEN ?12 ELSE low_stock_threshold END, sku=CASE WHEN ?14 THEN ?15 ELSE sku END, version=version+1, updated_at=datetime('now') WHERE id=?10 AND version=?13";
 
-    d1.prepare(query)
-        .bind(&[
+    // Товар, остаток и штрихкоды сохраняются одним batch. stock_guard после UPDATE
+    // откатывает всё, если версия успела измениться; после set_stock — если изменился остаток
+    let mut queries = vec![
+        d1.prepare(query).bind(&[
             name.into(),
             name_kk.into(),
             category_id.into(),
@@ -283,17 +834,232 @@ pub async fn update_product(mut req: Request, ctx: RouteContext<()>) -> Result<R
             images_json.into(),
             description.into(),
             description_kk.into(),
-            stock.into(),
-            id.into(),
-        ])?
-        .run()
-        .await?;
+            product_id.into(),
+            (low_stock_threshold.is_some() as i32).into(),
+            low_stock_threshold.flatten().into(),
+            version.into(),
+            (sku.is_some() as i32).into(),
+            sku.flatten().into(),
+        ])?,
+        inventory::stock_guard(&d1),
+    ];
+    // Остаток из формы — ручная корректировка: разница попадает в журнал движения
+    if let Some((stock, expected)) = stock_change {
+        queries.extend(inventory::set_stock(
+            &d1,
+            product_id,
+            stock,
+            Some(expected),
+            MovementReason::Correction,
+            Some(&actor),
+            None,
+        )?);
+        queries.push(inventory::stock_guard(&d1));
+    }
+    if let Some(barcodes) = &barcodes {
+        queries.extend(barcode_statements(&d1, Some(product_id), barcodes)?);
+    }
+    if current.category_id != Some(category_id) {
+        queries.push(attributes::purge_foreign_values(&d1, &[product_id])?);
+    }
 
-    Response::ok("Updated")
+    if let Err(e) = d1.batch(queries).await {
+        delete_uploads(&bucket, &uploaded).await;
+        if is_unique_violation(&e) {
+            return Response::error(unique_violation_message(&e), 409);
+        }
+        if !inventory::is_stock_shortage(&e) {
+            return Err(e);
+        }
+        // Кто-то успел сохранить товар или изменить остаток между проверкой и batch
+        return match find_product(&d1, product_id).await? {
+            Some(current) if current.version != version => {
+                versioning::conflict(&current, current.version)
+            }
+            Some(current) => Ok(Response::from_json(&serde_json::json!({
+                "error": "Остаток уже изменился, обновите карточку товара",
+                "stock": current.stock,
+            }))?
+            .with_status(409)),
+            None => Response::error("Товар не найден", 404),
+        };
+    }
+
+    let mut background: Vec<Job> = uploaded
+        .into_iter()
+        .map(|key| Job::ImageUploaded { key })
+        .collect();
+    if stock_change.is_some() {
+        background.push(Job::StockCheck {
+            product_ids: vec![product_id],
+        });
+    }
+
+    jobs::enqueue(&ctx, background);
+    versioning::with_etag(Response::ok("Updated")?, version + 1)
+}
+
+// Загруженные для несохранённой правки картинки больше не нужны
+pub async fn delete_uploads(bucket: &Bucket, keys: &[String]) {
+    for key in keys {
+        let _ = bucket.delete(key).await;
+    }
+}
+
+// PATCH товара (JSON): { "price": 990, "old_price": null, "version": 3 }.
+// Непереданные поля не меняются, null очищает. Остаток здесь не меняется —
+// для него есть приёмка, списание и инвентаризация
+pub async fn patch_product(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let product_id = ctx
+        .param("id")
+        .and_then(|s| s.parse::<i32>().ok())
+        .unwrap_or(0);
+    let body: serde_json::Value = req.json().await?;
+
+    if body.get("stock").is_some() {
+        return Response::error(
+            "Остаток меняется через /api/admin/inventory (приёмка, списание, инвентаризация)",
+            400,
+        );
+    }
+    let Some(version) = versioning::expected_version(&req, body.get("version")) else {
+        return versioning::version_required();
+    };
+
+    // Штрихкоды живут в отдельной таблице: { "barcodes": ["4870001234560"] }
+    let mut body = body;
+    let barcodes = match body.as_object_mut().and_then(|o| o.remove("barcodes")) {
+        None => None,
+        Some(serde_json::Value::Null) => Some(Vec::new()),
+        Some(serde_json::Value::Array(list)) => {
+            match validation::parse_barcodes(list.iter().filter_map(|v| v.as_str())) {
+                Ok(barcodes) if barcodes.len() == list.len() => Some(barcodes),
+                Ok(_) => return Response::error("barcodes: список разных строк", 400),
+                Err(e) => return Response::error(e, 400),
+            }
+        }
+        Some(_) => return Response::error("barcodes: ожидается список", 400),
+    };
+    let sku = match body.get("sku") {
+        Some(serde_json::Value::String(s)) if !s.trim().is_empty() => {
+            match validation::normalize_sku(s) {
+                Some(sku) => Some(sku),
+                None => return Response::error("Неверный артикул", 400),
+            }
+        }
+        _ => None,
+    };
+    // В базу идёт артикул в нормализованном виде
+    if let Some(sku) = &sku {
+        body["sku"] = serde_json::Value::String(sku.clone());
+    }
+    let parent_id = body.get("parent_id").and_then(|v| {
+        v.as_i64()
+            .or_else(|| v.as_str().and_then(|s| s.trim().parse().ok()))
+    });
+    if let Some(parent_id) = parent_id.filter(|id| *id > 0) {
+        if let Err(e) = check_parent(&d1, Some(product_id), parent_id as i32).await? {
+            return Response::error(e, 400);
+        }
+    }
+    if let Some(conflict) = identifier_conflict(
+        &d1,
+        Some(product_id),
+        sku.as_deref(),
+        barcodes.as_deref().unwrap_or_default(),
+    )
+    .await?
+    {
+        return Response::error(conflict, 409);
+    }
+
+    let patch = match patch::build(
+        &body,
+        &[
+            ("name", FieldKind::Text),
+            ("name_kk", FieldKind::NullableText),
+            ("category_id", FieldKind::NullableId),
+            ("price", FieldKind::Number),
+            ("old_price", FieldKind::NullableNumber),
+            ("unit", FieldKind::NullableText),
+            ("image", FieldKind::StringList),
+            ("description", FieldKind::NullableText),
+            ("description_kk", FieldKind::NullableText),
+            ("low_stock_threshold", FieldKind::NullableNumber),
+            ("is_hidden", FieldKind::Flag),
+            ("sku", FieldKind::NullableText),
+            ("parent_id", FieldKind::NullableId),
+            ("variant_label", FieldKind::NullableText),
+            ("variant_label_kk", FieldKind::NullableText),
+        ],
+    ) {
+        Ok(patch) => patch,
+        Err(e) => return Response::error(e, 400),
+    };
+    if patch.is_empty() && barcodes.is_none() {
+        return Response::error("Нет полей для изменения", 400);
+    }
+
+    // Одни штрихкоды тоже считаются правкой товара: версия растёт
+    let set_clause = if patch.is_empty() {
+        "version = version + 1".to_string()
+    } else {
+        format!("{}, version = version + 1", patch.set_clause())
+    };
+    let mut params = patch.params.clone();
+    params.push(product_id.into());
+    params.push(version.into());
+    // Штрихкоды — в одном batch с UPDATE: если версия не совпала, stock_guard откатывает всё
+    let mut queries = vec![
+        d1.prepare(format!(
+            "UPDATE products SET {}, updated_at = datetime('now') WHERE id = ? AND version = ? RETURNING *",
+            set_clause
+        ))
+        .bind(&params)?,
+        inventory::stock_guard(&d1),
+    ];
+    if let Some(barcodes) = &barcodes {
+        queries.extend(barcode_statements(&d1, Some(product_id), barcodes)?);
+    }
+    // Новая категория (или родитель) — характеристики старой категории не нужны
+    if patch.columns.contains(&"category_id") || patch.columns.contains(&"parent_id") {
+        queries.push(attributes::purge_foreign_values(&d1, &[product_id])?);
+    }
+    let results = match d1.batch(queries).await {
+        Ok(results) => results,
+        Err(e) if is_unique_violation(&e) => {
+            return Response::error(unique_violation_message(&e), 409);
+        }
+        Err(e) if inventory::is_stock_shortage(&e) => {
+            return match find_product(&d1, product_id).await? {
+                Some(current) => versioning::conflict(&current, current.version),
+                None => Response::error("Товар не найден", 404),
+            };
+        }
+        Err(e) => return Err(e),
+    };
+    let Some(product) = results[0].results::<Product>()?.into_iter().next() else {
+        return Response::error("Товар не найден", 404);
+    };
+
+    let mut products = [product];
+    attach_barcodes(&d1, &mut products).await?;
+    let [product] = products;
+
+    if patch.columns.contains(&"low_stock_threshold") {
+        jobs::enqueue(
+            &ctx,
+            vec![Job::StockCheck {
+                product_ids: vec![product.id],
+            }],
+        );
+    }
+    versioning::with_etag(Response::from_json(&product)?, product.version)
 }
 
 //получение товаров для корзины
-pub async fn get_cart_items(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn get_cart_items(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
     let body: serde_json::Value = req.json().await?;
 
@@ -319,6 +1085,278 @@ pub async fn get_cart_items(mut req: Request, ctx: RouteContext<()>) -> Result<R
 
     let statement = d1.prepare(&query).bind(&params)?;
     let result = statement.all().await?;
+    // Варианты — с названием и картинками родителя ("Молоко 1 л").
+    // Скрытые товары выпадают из корзины так же, как удалённые
+    let mut products = result.results::<Product>()?;
+    resolve_variants(&d1, &mut products).await?;
+    products.retain(|p| p.is_hidden == 0);
+
+    Response::from_json(&products)
+}
+
+// Максимум товаров в одной массовой операции (запросов в одном batch)
+const BULK_LIMIT: usize = 200;
+
+// id товаров для массовой операции: без повторов, не больше BULK_LIMIT
+fn bulk_ids(value: &serde_json::Value) -> std::result::Result<Vec<i32>, String> {
+    let mut ids: Vec<i32> = value
+        .as_array()
+        .map(|ids| {
+            ids.iter()
+                .filter_map(|v| {
+                    v.as_i64()
+                        .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+                })
+                .map(|id| id as i32)
+                .collect()
+        })
+        .unwrap_or_default();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.is_empty() {
+        return Err("Не выбраны товары (ids)".to_string());
+    }
+    if ids.len() > BULK_LIMIT {
+        return Err(format!("Не больше {} товаров за раз", BULK_LIMIT));
+    }
+    Ok(ids)
+}
+
+// Массовая операция над товарами одним batch:
+// { ids: [1, 2], operation, category_id?, percent?, amount?, old_price? }
+// operation: set_category | adjust_price (percent или amount) | set_old_price (число
+// или текущая цена, если не указано) | clear_old_price | hide | unhide | delete
+pub async fn bulk_update(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let ids = match bulk_ids(&body["ids"]) {
+        Ok(ids) => ids,
+        Err(e) => return Response::error(e, 400),
+    };
+
+    let operation = body["operation"].as_str().unwrap_or("").trim().to_string();
+    let update = |set: &str| {
+        format!(
+            "UPDATE products SET {}, version = version + 1, updated_at = datetime('now') WHERE id = ?",
+            set
+        )
+    };
+
+    // SQL для одного товара и параметры, которые идут перед id
+    let (sql, params): (String, Vec<wasm_bindgen::JsValue>) = match operation.as_str() {
+        "set_category" => {
+            let Some(category_id) = body["category_id"].as_i64().map(|id| id as i32) else {
+                return Response::error("Укажите category_id", 400);
+            };
+            let exists = d1
+                .prepare("SELECT id FROM categories WHERE id = ?")
+                .bind(&[category_id.into()])?
+                .first::<serde_json::Value>(None)
+                .await?
+                .is_some();
+            if !exists {
+                return Response::error("Категория не найдена", 404);
+            }
+            (update("category_id = ?"), vec![category_id.into()])
+        }
+        "adjust_price" => match (body["percent"].as_f64(), body["amount"].as_f64()) {
+            // Новая цена после округления должна остаться больше нуля, иначе товар пропускается
+            (Some(percent), None) if percent > -100.0 => (
+                format!(
+                    "{} AND ROUND(price * (1 + ? / 100.0), 2) > 0",
+                    update("price = ROUND(price * (1 + ? / 100.0), 2)")
+                ),
+                vec![percent.into()],
+            ),
+            (None, Some(amount)) => (
+                format!(
+                    "{} AND ROUND(price + ?, 2) > 0",
+                    update("price = ROUND(price + ?, 2)")
+                ),
+                vec![amount.into()],
+            ),
+            _ => {
+                return Response::error(
+                    "Укажите percent (больше -100) или amount, но не оба сразу",
+                    400,
+                )
+            }
+        },
+        "set_old_price" => match &body["old_price"] {
+            serde_json::Value::Null => (update("old_price = price"), vec![]),
+            value => match value.as_f64().filter(|p| *p > 0.0) {
+                Some(old_price) => (update("old_price = ?"), vec![old_price.into()]),
+                None => return Response::error("Неверное значение old_price", 400),
+            },
+        },
+        "clear_old_price" => (update("old_price = NULL"), vec![]),
+        "hide" => (update("is_hidden = 1"), vec![]),
+        "unhide" => (update("is_hidden = 0"), vec![]),
+        // Товар с вариантами не удаляем, чтобы варианты не остались без родителя
+        "delete" => (
+            "DELETE FROM products WHERE id = ? AND NOT EXISTS (SELECT 1 FROM products v WHERE v.parent_id = products.id)"
+                .to_string(),
+            vec![],
+        ),
+        _ => return Response::error("Неизвестная операция", 400),
+    };
+    // Для изменения цены условие стоит после id: параметр нужен ещё раз
+    let repeat_params = operation == "adjust_price";
 
-    Response::from_json(&result.results::<Product>()?)
+    // Список id одним JSON-параметром: у D1 ограничение в 100 параметров
+    let before: HashMap<i32, Product> = d1
+        .prepare("SELECT * FROM products WHERE id IN (SELECT value FROM json_each(?))")
+        .bind(&[serde_json::to_string(&ids)?.into()])?
+        .all()
+        .await?
+        .results::<Product>()?
+        .into_iter()
+        .map(|p| (p.id, p))
+        .collect();
+
+    let sql = format!("{} RETURNING *", sql);
+    let mut queries = Vec::new();
+    let mut batch_ids = Vec::new();
+    for &id in &ids {
+        if !before.contains_key(&id) {
+            continue;
+        }
+        let mut bind = params.clone();
+        bind.push(id.into());
+        if repeat_params {
+            bind.extend(params.iter().cloned());
+        }
+        queries.push(d1.prepare(&sql).bind(&bind)?);
+        batch_ids.push(id);
+    }
+    // Служебные запросы идут после всех UPDATE/DELETE, чтобы результаты совпадали
+    // с batch_ids по индексу
+    if operation == "set_category" && !batch_ids.is_empty() {
+        queries.push(attributes::purge_foreign_values(&d1, &batch_ids)?);
+    }
+    // Штрихкоды и характеристики удалённых товаров — в том же batch. Товар с вариантами
+    // не удаляется, поэтому чистим только те id, которых больше нет в products
+    if operation == "delete" && !batch_ids.is_empty() {
+        let list = serde_json::to_string(&batch_ids)?;
+        for table in ["product_barcodes", "product_attribute_values"] {
+            queries.push(
+                d1.prepare(format!(
+                    "DELETE FROM {} WHERE product_id IN (SELECT value FROM json_each(?))
+                     AND NOT EXISTS (SELECT 1 FROM products WHERE products.id = {}.product_id)",
+                    table, table
+                ))
+                .bind(&[list.clone().into()])?,
+            );
+        }
+    }
+
+    let results = if queries.is_empty() {
+        Vec::new()
+    } else {
+        d1.batch(queries).await?
+    };
+
+    let mut report = Vec::new();
+    let mut changed = 0;
+    let mut deleted = Vec::new();
+    for id in &ids {
+        let Some(product) = before.get(id) else {
+            report.push(serde_json::json!({ "id": id, "status": "not_found" }));
+            continue;
+        };
+        let after = batch_ids
+            .iter()
+            .position(|b| b == id)
+            .and_then(|i| results.get(i))
+            .and_then(|r| r.results::<Product>().ok())
+            .and_then(|rows| rows.into_iter().next());
+
+        match after {
+            Some(after) => {
+                changed += 1;
+                if operation == "delete" {
+                    deleted.push(*id);
+                }
+                report.push(serde_json::json!({
+                    "id": id,
+                    "status": if operation == "delete" { "deleted" } else { "updated" },
+                    "before": { "price": product.price, "old_price": product.old_price, "category_id": product.category_id, "is_hidden": product.is_hidden },
+                    "after": { "price": after.price, "old_price": after.old_price, "category_id": after.category_id, "is_hidden": after.is_hidden },
+                }));
+            }
+            None => report.push(serde_json::json!({
+                "id": id,
+                "status": "skipped",
+                "error": if operation == "delete" {
+                    "У товара есть варианты — сначала удалите их"
+                } else {
+                    "Цена после изменения должна быть больше нуля"
+                },
+            })),
+        }
+    }
+
+    // Картинки удалённых товаров больше не нужны
+    if operation == "delete" {
+        let bucket = ctx.env.bucket("akniet_bucket")?;
+        for product in deleted.iter().filter_map(|id| before.get(id)) {
+            let images: Vec<String> = product
+                .image
+                .as_deref()
+                .and_then(|json| serde_json::from_str(json).ok())
+                .unwrap_or_default();
+            for url in images {
+                if let Some(file_name) = url.split('/').next_back() {
+                    let _ = bucket.delete(file_name).await;
+                }
+            }
+        }
+    }
+
+    Response::from_json(&serde_json::json!({
+        "operation": operation,
+        "requested": ids.len(),
+        "changed": changed,
+        "items": report,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulk_ids_up_to_limit() {
+        let ids: Vec<i32> = (1..=BULK_LIMIT as i32).collect();
+        let parsed = bulk_ids(&serde_json::json!(ids)).unwrap();
+        assert_eq!(parsed.len(), BULK_LIMIT);
+        // Одним JSON-параметром, а не параметром на каждый id
+        let list: Vec<i32> =
+            serde_json::from_str(&serde_json::to_string(&parsed).unwrap()).unwrap();
+        assert_eq!(list, ids);
+    }
+
+    #[test]
+    fn bulk_ids_over_limit() {
+        let ids: Vec<i32> = (1..=BULK_LIMIT as i32 + 1).collect();
+        assert!(bulk_ids(&serde_json::json!(ids)).is_err());
+    }
+
+    #[test]
+    fn bulk_ids_dedup_before_limit() {
+        let mut ids: Vec<serde_json::Value> = (1..=BULK_LIMIT as i32).map(|id| id.into()).collect();
+        ids.push("1".into());
+        ids.push(2.into());
+        let parsed = bulk_ids(&serde_json::Value::Array(ids)).unwrap();
+        assert_eq!(parsed.len(), BULK_LIMIT);
+        assert_eq!(parsed[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn bulk_ids_required() {
+        assert!(bulk_ids(&serde_json::json!([])).is_err());
+        assert!(bulk_ids(&serde_json::json!(null)).is_err());
+        assert!(bulk_ids(&serde_json::json!(["abc"])).is_err());
+    }
 }
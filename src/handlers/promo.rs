@@ -2,7 +2,7 @@ use crate::models::PromoCode;
 use worker::*;
 
 // 1. Проверка промокода (для корзины)
-pub async fn check_promo(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn check_promo(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
     let body: serde_json::Value = req.json().await?;
 
@@ -19,7 +19,7 @@ pub async fn check_promo(mut req: Request, ctx: RouteContext<()>) -> Result<Resp
 }
 
 // 2. Список промокодов
-pub async fn list_promos(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn list_promos(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
     let statement = d1.prepare("SELECT * FROM promocodes ORDER BY id DESC");
     let result = statement.all().await?;
@@ -27,11 +27,11 @@ pub async fn list_promos(_req: Request, ctx: RouteContext<()>) -> Result<Respons
 }
 
 // 3. Создание промокода
-pub async fn create_promo(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn create_promo(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
     let promo: PromoCode = req.json().await?;
 
-    d1.prepare("INSERT INTO promocodes (code, discount, is_active) VALUES (?, ?, 1)")
+    d1.prepare("INSERT INTO promocodes (code, discount, is_active, created_at, updated_at) VALUES (?, ?, 1, datetime('now'), datetime('now'))")
         .bind(&[promo.code.to_uppercase().into(), promo.discount.into()])?
         .run()
         .await?;
@@ -40,7 +40,7 @@ pub async fn create_promo(mut req: Request, ctx: RouteContext<()>) -> Result<Res
 }
 
 // 4. Удаление промокода
-pub async fn delete_promo(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn delete_promo(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
     let d1 = ctx.env.d1("akniet_db")?;
     let id = ctx.param("id").unwrap();
 
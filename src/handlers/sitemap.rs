@@ -0,0 +1,190 @@
+use crate::clock;
+use crate::feeds::escape_xml;
+use crate::handlers::products::STOREFRONT_VISIBLE;
+use crate::storefront::{self, Storefront, LANGUAGES};
+use serde::Deserialize;
+use worker::*;
+
+// Ссылок в одном файле sitemap. Протокол допускает 50 000, но с hreflang
+// каждая запись втрое длиннее — держим файлы небольшими
+const PAGE_SIZE: i32 = 10_000;
+
+const CACHE_CONTROL: &str = "public, max-age=3600";
+
+#[derive(Deserialize)]
+struct Page {
+    id: i32,
+    slug: Option<String>,
+    updated_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Count {
+    count: i32,
+}
+
+// Те же условия, что у витрины: в наличии и не скрыт; у вариантов нет своих страниц
+fn visible_products() -> String {
+    format!("FROM products WHERE {}", STOREFRONT_VISIBLE)
+}
+
+fn xml_response(xml: String) -> Result<Response> {
+    let mut response = Response::ok(xml)?;
+    let headers = response.headers_mut();
+    headers.set("Content-Type", "application/xml; charset=utf-8")?;
+    headers.set("Cache-Control", CACHE_CONTROL)?;
+    Ok(response)
+}
+
+// <url> с альтернативными языковыми версиями; x-default ведёт на русскую
+fn url_entry(store: &Storefront, path: &str, updated_at: Option<&str>) -> String {
+    let mut xml = format!(
+        "<url>\n<loc>{}</loc>\n",
+        escape_xml(&store.localized(path, "ru"))
+    );
+    if let Some(updated_at) = updated_at {
+        xml.push_str(&format!(
+            "<lastmod>{}</lastmod>\n",
+            clock::to_rfc3339(updated_at)
+        ));
+    }
+    for lang in LANGUAGES {
+        xml.push_str(&format!(
+            "<xhtml:link rel=\"alternate\" hreflang=\"{}\" href=\"{}\"/>\n",
+            lang,
+            escape_xml(&store.localized(path, lang))
+        ));
+    }
+    xml.push_str(&format!(
+        "<xhtml:link rel=\"alternate\" hreflang=\"x-default\" href=\"{}\"/>\n</url>\n",
+        escape_xml(&store.localized(path, "ru"))
+    ));
+    xml
+}
+
+fn urlset(entries: impl Iterator<Item = String>) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" xmlns:xhtml=\"http://www.w3.org/1999/xhtml\">\n",
+    );
+    for entry in entries {
+        xml.push_str(&entry);
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+async fn categories(d1: &D1Database) -> Result<Vec<Page>> {
+    d1.prepare("SELECT id, slug, updated_at FROM categories ORDER BY id")
+        .all()
+        .await?
+        .results::<Page>()
+}
+
+// Страница за пределами i32 заведомо пустая
+async fn products(d1: &D1Database, page: i32) -> Result<Vec<Page>> {
+    let Some(offset) = (page - 1).checked_mul(PAGE_SIZE) else {
+        return Ok(Vec::new());
+    };
+    d1.prepare(format!(
+        "SELECT id, updated_at {} ORDER BY id LIMIT ? OFFSET ?",
+        visible_products()
+    ))
+    .bind(&[PAGE_SIZE.into(), offset.into()])?
+    .all()
+    .await?
+    .results::<Page>()
+}
+
+fn category_entries<'a>(
+    store: &'a Storefront,
+    categories: &'a [Page],
+) -> impl Iterator<Item = String> + 'a {
+    categories.iter().map(|c| {
+        // Без slug витрина открывает категорию по id
+        let slug = c.slug.clone().unwrap_or_else(|| c.id.to_string());
+        url_entry(
+            store,
+            &storefront::category_path(&slug),
+            c.updated_at.as_deref(),
+        )
+    })
+}
+
+fn product_entries<'a>(
+    store: &'a Storefront,
+    products: &'a [Page],
+) -> impl Iterator<Item = String> + 'a {
+    products.iter().map(|p| {
+        url_entry(
+            store,
+            &storefront::product_path(p.id),
+            p.updated_at.as_deref(),
+        )
+    })
+}
+
+// Небольшой каталог — один файл; большой — индекс со ссылками на части
+pub async fn sitemap(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let store = Storefront::from_env(&ctx.env);
+
+    let total = d1
+        .prepare(format!("SELECT COUNT(*) AS count {}", visible_products()))
+        .first::<Count>(None)
+        .await?
+        .map(|c| c.count)
+        .unwrap_or(0);
+    let categories = categories(&d1).await?;
+
+    if total + categories.len() as i32 <= PAGE_SIZE {
+        let products = products(&d1, 1).await?;
+        let entries =
+            category_entries(&store, &categories).chain(product_entries(&store, &products));
+        return xml_response(urlset(entries));
+    }
+
+    // Части sitemap отдаёт этот же воркер
+    let url = req.url()?;
+    let origin = url.origin().ascii_serialization();
+    let mut files = vec![format!("{}/sitemaps/categories.xml", origin)];
+    let pages = (total + PAGE_SIZE - 1) / PAGE_SIZE;
+    files.extend((1..=pages).map(|page| format!("{}/sitemaps/products/{}.xml", origin, page)));
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for file in files {
+        xml.push_str(&format!(
+            "<sitemap>\n<loc>{}</loc>\n</sitemap>\n",
+            escape_xml(&file)
+        ));
+    }
+    xml.push_str("</sitemapindex>\n");
+    xml_response(xml)
+}
+
+pub async fn sitemap_categories(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let store = Storefront::from_env(&ctx.env);
+    let categories = categories(&d1).await?;
+    xml_response(urlset(category_entries(&store, &categories)))
+}
+
+// /sitemaps/products/2.xml — вторая страница товаров
+pub async fn sitemap_products(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let page = ctx
+        .param("page")
+        .and_then(|p| p.trim_end_matches(".xml").parse::<i32>().ok())
+        .filter(|p| *p >= 1);
+    let Some(page) = page else {
+        return Response::error("Not found", 404);
+    };
+
+    let d1 = ctx.env.d1("akniet_db")?;
+    let store = Storefront::from_env(&ctx.env);
+    let products = products(&d1, page).await?;
+    if products.is_empty() {
+        return Response::error("Not found", 404);
+    }
+    xml_response(urlset(product_entries(&store, &products)))
+}
@@ -0,0 +1,343 @@
+use crate::clock;
+use crate::models::{StoreClosure, StoreHours};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime};
+use chrono_tz::Tz;
+use worker::*;
+
+// Состояние магазина на текущий момент (время местное, по STORE_TIMEZONE)
+pub struct StoreStatus {
+    pub is_open: bool,
+    pub closes_at: Option<DateTime<Tz>>,
+    pub next_open_at: Option<DateTime<Tz>>,
+    pub closure: Option<StoreClosure>,
+}
+
+impl StoreStatus {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "is_open": self.is_open,
+            "now": clock::format(&clock::now_local()),
+            "closes_at": self.closes_at.map(|t| clock::format(&t)),
+            "next_open_at": self.next_open_at.map(|t| clock::format(&t)),
+            "closure": self.closure,
+        })
+    }
+}
+
+pub async fn store_status(d1: &D1Database) -> Result<StoreStatus> {
+    let now = clock::now_local();
+    let today = now.date_naive();
+
+    let results = d1
+        .batch(vec![
+            d1.prepare("SELECT * FROM store_hours"),
+            d1.prepare("SELECT * FROM store_closures WHERE date BETWEEN ? AND ? ORDER BY date")
+                .bind(&[
+                    (today - Duration::days(1)).to_string().into(),
+                    (today + Duration::days(31)).to_string().into(),
+                ])?,
+        ])
+        .await?;
+    let hours = results[0].results::<StoreHours>()?;
+    let closures = results[1].results::<StoreClosure>()?;
+    Ok(status_at(now, &hours, &closures))
+}
+
+// Состояние магазина в момент now. closures — нерабочие дни со вчера на месяц вперёд
+fn status_at(now: DateTime<Tz>, hours: &[StoreHours], closures: &[StoreClosure]) -> StoreStatus {
+    let today = now.date_naive();
+    let is_closure = |date: NaiveDate| closures.iter().any(|c| c.date == date.to_string());
+    let today_closure = closures
+        .iter()
+        .find(|c| c.date == today.to_string())
+        .cloned();
+
+    // Часы не настроены — магазин работает круглосуточно, кроме нерабочих дней
+    if hours.is_empty() {
+        let midnight = |date: NaiveDate| clock::local_datetime(date, NaiveTime::MIN);
+        let next_day = |closed: bool| {
+            (1..=31)
+                .map(|offset| today + Duration::days(offset))
+                .find(|date| is_closure(*date) == closed)
+                .and_then(midnight)
+        };
+        return if today_closure.is_some() {
+            StoreStatus {
+                is_open: false,
+                closes_at: None,
+                next_open_at: next_day(false),
+                closure: today_closure,
+            }
+        } else {
+            StoreStatus {
+                is_open: true,
+                closes_at: next_day(true),
+                next_open_at: None,
+                closure: None,
+            }
+        };
+    }
+
+    // Перебираем рабочие интервалы со вчерашнего дня (ночная смена) на месяц вперёд
+    for offset in -1..=31 {
+        let date = today + Duration::days(offset);
+        if is_closure(date) {
+            continue;
+        }
+        let weekday = date.weekday().number_from_monday() as i32;
+        let Some(day) = hours.iter().find(|h| h.weekday == weekday) else {
+            continue;
+        };
+        if day.is_closed != 0 {
+            continue;
+        }
+
+        let open = day.open_time.as_deref().and_then(clock::parse_time);
+        let close = day.close_time.as_deref().and_then(clock::parse_time);
+        let (Some(open), Some(close)) = (open, close) else {
+            continue;
+        };
+
+        // Закрытие раньше открытия — работаем после полуночи
+        let close_date = if close <= open {
+            date + Duration::days(1)
+        } else {
+            date
+        };
+        let (Some(start), Some(end)) = (
+            clock::local_datetime(date, open),
+            clock::local_datetime(close_date, close),
+        ) else {
+            continue;
+        };
+
+        if start <= now && now < end {
+            return StoreStatus {
+                is_open: true,
+                closes_at: Some(end),
+                next_open_at: None,
+                closure: None,
+            };
+        }
+        if start > now {
+            return StoreStatus {
+                is_open: false,
+                closes_at: None,
+                next_open_at: Some(start),
+                closure: today_closure,
+            };
+        }
+    }
+
+    StoreStatus {
+        is_open: false,
+        closes_at: None,
+        next_open_at: None,
+        closure: today_closure,
+    }
+}
+
+// 1. Открыт ли магазин сейчас и когда откроется
+pub async fn get_status(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let status = store_status(&d1).await?;
+    Response::from_json(&status.to_json())
+}
+
+// 2. Часы работы
+pub async fn list_hours(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let hours = d1
+        .prepare("SELECT * FROM store_hours ORDER BY weekday")
+        .all()
+        .await?
+        .results::<StoreHours>()?;
+    Response::from_json(&hours)
+}
+
+// 3. Сохранение часов работы: [{ weekday, open_time, close_time, is_closed }]
+pub async fn save_hours(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+    let days = body.as_array().ok_or("Expected array")?;
+
+    let mut queries = Vec::new();
+    for day in days {
+        let weekday = day["weekday"].as_i64().unwrap_or(0) as i32;
+        if !(1..=7).contains(&weekday) {
+            return Response::error("День недели должен быть от 1 (пн) до 7 (вс)", 400);
+        }
+
+        let is_closed = day["is_closed"].as_bool().unwrap_or(false);
+        let open = day["open_time"].as_str().and_then(clock::parse_time);
+        let close = day["close_time"].as_str().and_then(clock::parse_time);
+        if !is_closed && (open.is_none() || close.is_none()) {
+            return Response::error("Время работы должно быть в формате ЧЧ:ММ", 400);
+        }
+
+        queries.push(
+            d1.prepare(
+                "INSERT INTO store_hours (weekday, open_time, close_time, is_closed) VALUES (?, ?, ?, ?)
+                 ON CONFLICT(weekday) DO UPDATE SET open_time = excluded.open_time, close_time = excluded.close_time, is_closed = excluded.is_closed",
+            )
+            .bind(&[
+                weekday.into(),
+                open.map(|t| t.format("%H:%M").to_string()).into(),
+                close.map(|t| t.format("%H:%M").to_string()).into(),
+                (is_closed as i32).into(),
+            ])?,
+        );
+    }
+
+    if !queries.is_empty() {
+        d1.batch(queries).await?;
+    }
+
+    Response::ok("Updated")
+}
+
+// 4. Нерабочие дни (от сегодняшнего)
+pub async fn list_closures(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let closures = d1
+        .prepare("SELECT * FROM store_closures WHERE date >= ? ORDER BY date")
+        .bind(&[clock::today().to_string().into()])?
+        .all()
+        .await?
+        .results::<StoreClosure>()?;
+    Response::from_json(&closures)
+}
+
+// 5. Добавление нерабочего дня: { date: "2024-12-16", reason, reason_kk }
+pub async fn create_closure(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let body: serde_json::Value = req.json().await?;
+
+    let Some(date) = body["date"].as_str().and_then(clock::parse_date) else {
+        return Response::error("Дата должна быть в формате ГГГГ-ММ-ДД", 400);
+    };
+
+    let result = d1
+        .prepare("INSERT INTO store_closures (date, reason, reason_kk) VALUES (?, ?, ?)")
+        .bind(&[
+            date.to_string().into(),
+            body["reason"].as_str().map(|s| s.trim().to_string()).into(),
+            body["reason_kk"]
+                .as_str()
+                .map(|s| s.trim().to_string())
+                .into(),
+        ])?
+        .run()
+        .await;
+
+    match result {
+        Ok(_) => Response::ok("Created"),
+        Err(e) => {
+            if e.to_string().contains("UNIQUE constraint failed") {
+                Response::error("Этот день уже отмечен нерабочим", 400)
+            } else {
+                Response::error(format!("D1 Error: {}", e), 500)
+            }
+        }
+    }
+}
+
+// 6. Удаление нерабочего дня
+pub async fn delete_closure(_req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let d1 = ctx.env.d1("akniet_db")?;
+    let id = ctx.param("id").map(|s| s.to_string()).unwrap_or_default();
+
+    d1.prepare("DELETE FROM store_closures WHERE id = ?")
+        .bind(&[id.into()])?
+        .run()
+        .await?;
+
+    Response::ok("Deleted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2026-10-19 — понедельник
+    fn at(date: &str, time: &str) -> DateTime<Tz> {
+        clock::local_datetime(
+            clock::parse_date(date).unwrap(),
+            clock::parse_time(time).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn closure(date: &str) -> StoreClosure {
+        StoreClosure {
+            id: None,
+            date: date.to_string(),
+            reason: Some("Санитарный день".to_string()),
+            reason_kk: None,
+        }
+    }
+
+    fn every_day(open: &str, close: &str) -> Vec<StoreHours> {
+        (1..=7)
+            .map(|weekday| StoreHours {
+                weekday,
+                open_time: Some(open.to_string()),
+                close_time: Some(close.to_string()),
+                is_closed: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_hours_means_open() {
+        let status = status_at(at("2026-10-19", "03:00"), &[], &[]);
+        assert!(status.is_open);
+        assert!(status.closes_at.is_none());
+        assert!(status.closure.is_none());
+    }
+
+    #[test]
+    fn no_hours_closure_today() {
+        let status = status_at(at("2026-10-19", "12:00"), &[], &[closure("2026-10-19")]);
+        assert!(!status.is_open);
+        assert_eq!(status.next_open_at, Some(at("2026-10-20", "00:00")));
+        assert_eq!(status.closure.unwrap().date, "2026-10-19");
+    }
+
+    #[test]
+    fn no_hours_closure_next_week() {
+        let status = status_at(at("2026-10-19", "12:00"), &[], &[closure("2026-10-26")]);
+        assert!(status.is_open);
+        assert_eq!(status.closes_at, Some(at("2026-10-26", "00:00")));
+        assert!(status.closure.is_none());
+    }
+
+    #[test]
+    fn closure_today_skips_to_next_working_day() {
+        let hours = every_day("09:00", "21:00");
+        let status = status_at(at("2026-10-19", "12:00"), &hours, &[closure("2026-10-19")]);
+        assert!(!status.is_open);
+        assert_eq!(status.next_open_at, Some(at("2026-10-20", "09:00")));
+        assert!(status.closure.is_some());
+    }
+
+    #[test]
+    fn overnight_shift() {
+        let hours = every_day("20:00", "02:00");
+
+        // Смена, начатая вчера, ещё идёт
+        let status = status_at(at("2026-10-19", "01:00"), &hours, &[]);
+        assert!(status.is_open);
+        assert_eq!(status.closes_at, Some(at("2026-10-19", "02:00")));
+
+        // Днём закрыто до вечерней смены
+        let status = status_at(at("2026-10-19", "12:00"), &hours, &[]);
+        assert!(!status.is_open);
+        assert_eq!(status.next_open_at, Some(at("2026-10-19", "20:00")));
+
+        // Вечером открыто до 02:00 следующего дня
+        let status = status_at(at("2026-10-19", "23:30"), &hours, &[]);
+        assert!(status.is_open);
+        assert_eq!(status.closes_at, Some(at("2026-10-20", "02:00")));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::clock;
+use crate::handlers::inventory::{self, StockLevel};
+use crate::models::{Order, OrderItem, Product};
+use crate::notify;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+// Привязка очереди в wrangler.toml
+const QUEUE_BINDING: &str = "JOBS";
+// Пауза перед повтором задачи, которая завершилась ошибкой
+const RETRY_DELAY_SECONDS: u32 = 30;
+
+// Фоновые задачи. В очереди лежат как JSON: { "type": "order_notification", "order_id": 42 }
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Job {
+    // Уведомление магазину о новом заказе
+    OrderNotification { order_id: i32 },
+    // Учёт заказа в дневной статистике продаж
+    OrderAnalytics { order_id: i32 },
+    // Проверка остатков после их изменения (оповещения о нехватке)
+    StockCheck { product_ids: Vec<i32> },
+    // Загруженная в R2 картинка: тип содержимого и кэширование
+    ImageUploaded { key: String },
+}
+
+// Ставит задачи в очередь в фоне, не задерживая ответ.
+// Без привязки очереди (например, при локальном запуске) задачи выполняются здесь же
+pub fn enqueue(ctx: &RouteContext<Context>, jobs: Vec<Job>) {
+    if jobs.is_empty() {
+        return;
+    }
+
+    let env = ctx.env.clone();
+    ctx.data.wait_until(async move {
+        let sent = match env.queue(QUEUE_BINDING) {
+            Ok(queue) => queue.send_batch(jobs.iter()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            console_warn!("queue unavailable, running jobs inline: {}", e);
+            for job in &jobs {
+                if let Err(e) = run(&env, job).await {
+                    console_error!("job {:?} failed: {}", job, e);
+                }
+            }
+        }
+    });
+}
+
+// Обработчик очереди. Ошибка — повтор через RETRY_DELAY_SECONDS;
+// после max_retries Cloudflare перекладывает сообщение в очередь *-dlq,
+// её сообщения сохраняются в queue_dead_letters для разбора вручную
+pub async fn consume(batch: MessageBatch<serde_json::Value>, env: &Env) -> Result<()> {
+    let queue = batch.queue();
+    let dead_letters = queue.ends_with("-dlq");
+    let d1 = env.d1("akniet_db")?;
+    let retry = QueueRetryOptionsBuilder::new()
+        .with_delay_seconds(RETRY_DELAY_SECONDS)
+        .build();
+
+    for message in batch.messages()? {
+        if dead_letters {
+            match save_dead_letter(&d1, &queue, &message.id(), message.body(), None).await {
+                Ok(()) => message.ack(),
+                Err(e) => {
+                    console_error!("dead letter {} not saved: {}", message.id(), e);
+                    message.retry_with_options(&retry);
+                }
+            }
+            continue;
+        }
+
+        // Сообщение неизвестного формата повторять бессмысленно
+        let job = match serde_json::from_value::<Job>(message.body().clone()) {
+            Ok(job) => job,
+            Err(e) => {
+                let error = format!("invalid job: {}", e);
+                console_error!("{}", error);
+                save_dead_letter(&d1, &queue, &message.id(), message.body(), Some(&error)).await?;
+                message.ack();
+                continue;
+            }
+        };
+
+        match run(env, &job).await {
+            Ok(()) => message.ack(),
+            Err(e) => {
+                console_error!("job {:?} failed: {}", job, e);
+                message.retry_with_options(&retry);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn save_dead_letter(
+    d1: &D1Database,
+    queue: &str,
+    message_id: &str,
+    body: &serde_json::Value,
+    error: Option<&str>,
+) -> Result<()> {
+    d1.prepare(
+        "INSERT INTO queue_dead_letters (queue, message_id, body, error, created_at) VALUES (?, ?, ?, ?, datetime('now'))",
+    )
+    .bind(&[
+        queue.into(),
+        message_id.into(),
+        body.to_string().into(),
+        error.map(|e| e.to_string()).into(),
+    ])?
+    .run()
+    .await?;
+    Ok(())
+}
+
+async fn run(env: &Env, job: &Job) -> Result<()> {
+    match job {
+        Job::OrderNotification { order_id } => notify_order(env, *order_id).await,
+        Job::OrderAnalytics { order_id } => record_sale(env, *order_id).await,
+        Job::StockCheck { product_ids } => check_stock(env, product_ids).await,
+        Job::ImageUploaded { key } => process_image(env, key).await,
+    }
+}
+
+async fn notify_order(env: &Env, order_id: i32) -> Result<()> {
+    let d1 = env.d1("akniet_db")?;
+    let Some(order) = d1
+        .prepare("SELECT * FROM orders WHERE id = ?")
+        .bind(&[order_id.into()])?
+        .first::<Order>(None)
+        .await?
+    else {
+        // Заказ успели удалить — уведомлять не о чем
+        return Ok(());
+    };
+
+    let items: Vec<OrderItem> = serde_json::from_str(&order.items_json).unwrap_or_default();
+    notify::dispatch(env, &notify::new_order(&order, &items)).await
+}
+
+// Дневная статистика по дате заказа в часовом поясе магазина.
+// analytics_recorded_at не даёт учесть заказ дважды при повторе сообщения
+async fn record_sale(env: &Env, order_id: i32) -> Result<()> {
+    let d1 = env.d1("akniet_db")?;
+    let Some(order) = d1
+        .prepare("SELECT * FROM orders WHERE id = ? AND analytics_recorded_at IS NULL")
+        .bind(&[order_id.into()])?
+        .first::<Order>(None)
+        .await?
+    else {
+        return Ok(());
+    };
+
+    let day = clock::local_date(&order.created_at)
+        .unwrap_or_else(clock::today)
+        .to_string();
+    let items: Vec<OrderItem> = serde_json::from_str(&order.items_json).unwrap_or_default();
+
+    let mut queries = vec![d1
+        .prepare(
+            "INSERT INTO sales_daily (day, orders_count, revenue, delivery_revenue) VALUES (?, 1, ?, ?)
+             ON CONFLICT(day) DO UPDATE SET orders_count = orders_count + 1,
+                 revenue = revenue + excluded.revenue,
+                 delivery_revenue = delivery_revenue + excluded.delivery_revenue",
+        )
+        .bind(&[
+            day.clone().into(),
+            order.total_price.into(),
+            order.delivery_fee.into(),
+        ])?];
+
+    for item in &items {
+        queries.push(
+            d1.prepare(
+                "INSERT INTO product_sales_daily (day, product_id, quantity, revenue) VALUES (?, ?, ?, ?)
+                 ON CONFLICT(day, product_id) DO UPDATE SET quantity = quantity + excluded.quantity,
+                     revenue = revenue + excluded.revenue",
+            )
+            .bind(&[
+                day.clone().into(),
+                item.id.into(),
+                item.quantity.into(),
+                (item.price * item.quantity).into(),
+            ])?,
+        );
+    }
+
+    queries.push(
+        d1.prepare("UPDATE orders SET analytics_recorded_at = datetime('now') WHERE id = ?")
+            .bind(&[order_id.into()])?,
+    );
+
+    d1.batch(queries).await?;
+    Ok(())
+}
+
+// Пересчёт уровня остатка после его изменения. Оповещаем, только когда уровень
+// стал хуже отправленного ранее (норма -> мало -> закончился); при пополнении
+// отметка сбрасывается, и следующее падение снова вызовет оповещение
+async fn check_stock(env: &Env, product_ids: &[i32]) -> Result<()> {
+    if product_ids.is_empty() {
+        return Ok(());
+    }
+
+    let d1 = env.d1("akniet_db")?;
+    let default = inventory::default_threshold(env);
+    let placeholders = product_ids
+        .iter()
+        .map(|_| "?")
+        .collect::<Vec<_>>()
+        .join(",");
+    let params: Vec<wasm_bindgen::JsValue> = product_ids.iter().map(|&id| id.into()).collect();
+
+    let products = d1
+        .prepare(format!(
+            "SELECT * FROM products WHERE id IN ({})",
+            placeholders
+        ))
+        .bind(&params)?
+        .all()
+        .await?
+        .results::<Product>()?;
+
+    let mut alerts = Vec::new();
+    let mut updates = Vec::new();
+    for product in products {
+        let level = inventory::stock_level(&product, default);
+        let previous = product.stock_alert.as_deref().and_then(StockLevel::parse);
+        if level == previous {
+            continue;
+        }
+
+        updates.push(
+            d1.prepare("UPDATE products SET stock_alert = ? WHERE id = ?")
+                .bind(&[level.map(|l| l.as_str()).into(), product.id.into()])?,
+        );
+        if let Some(level) = level.filter(|l| previous.is_none_or(|p| l > &p)) {
+            alerts.push((product, level));
+        }
+    }
+
+    if !alerts.is_empty() {
+        let threshold = |p: &Product| p.low_stock_threshold.or(default);
+        notify::dispatch(env, &notify::stock_alert(&alerts, threshold)).await?;
+    }
+    // Отметки сохраняем только после отправки: dispatch вернул ошибку — выходим
+    // без них, и повтор сообщения из очереди оповестит снова
+    if !updates.is_empty() {
+        d1.batch(updates).await?;
+    }
+    Ok(())
+}
+
+// Тип картинки по первым байтам файла
+fn image_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if bytes.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else {
+        None
+    }
+}
+
+// Файлы загружаются без метаданных: проставляем настоящий Content-Type
+// и долгий кэш (имена уникальные, содержимое не меняется)
+async fn process_image(env: &Env, key: &str) -> Result<()> {
+    let bucket = env.bucket("akniet_bucket")?;
+    let Some(object) = bucket.get(key).execute().await? else {
+        return Ok(());
+    };
+    let Some(body) = object.body() else {
+        return Ok(());
+    };
+    let bytes = body.bytes().await?;
+
+    let Some(content_type) = image_type(&bytes) else {
+        console_warn!("uploaded file {} is not a supported image", key);
+        return Ok(());
+    };
+    if object.http_metadata().content_type.as_deref() == Some(content_type) {
+        return Ok(());
+    }
+
+    bucket
+        .put(key, bytes)
+        .http_metadata(HttpMetadata {
+            content_type: Some(content_type.to_string()),
+            cache_control: Some("public, max-age=31536000, immutable".to_string()),
+            ..Default::default()
+        })
+        .execute()
+        .await?;
+    Ok(())
+}
@@ -1,32 +1,93 @@
+mod clock;
+mod csv;
+mod feeds;
+mod geo;
 mod handlers;
+mod jobs;
 mod models;
+mod notify;
+mod patch;
+mod storefront;
+mod validation;
+mod versioning;
 
 use worker::*;
 
 #[event(fetch)]
-async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+async fn main(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    clock::configure(&env);
+
     let cors = Cors::default()
         .with_origins(vec!["*"])
-        .with_methods(vec![Method::Get, Method::Post, Method::Options])
-        .with_allowed_headers(vec!["Content-Type", "Authorization"])
+        .with_methods(vec![
+            Method::Get,
+            Method::Post,
+            Method::Patch,
+            Method::Options,
+        ])
+        .with_allowed_headers(vec![
+            "Content-Type",
+            "Authorization",
+            "Idempotency-Key",
+            "If-Match",
+        ])
+        .with_exposed_headers(vec![
+            "ETag",
+            "Content-Disposition",
+            "X-Total-Count",
+            "X-Page",
+            "X-Per-Page",
+        ])
         .with_max_age(3600);
 
-    let router = Router::new();
+    // Context нужен обработчикам для фоновых задач (wait_until)
+    let router = Router::with_data(ctx);
 
     router
         .options("/api/cart-items", |_req, _ctx| {
             Response::empty()?.with_cors(&Cors::default().with_origins(vec!["*"]))
         })
         .options("/api/create-order", |_req, _ctx| {
-            Response::empty()?.with_cors(&Cors::default().with_origins(vec!["*"]))
+            Response::empty()?.with_cors(
+                &Cors::default()
+                    .with_origins(vec!["*"])
+                    .with_methods(vec![Method::Post, Method::Options])
+                    .with_allowed_headers(vec!["Content-Type", "Idempotency-Key"]),
+            )
         })
         .options("/api/check-promo", |_req, _ctx| {
             Response::empty()?.with_cors(&Cors::default().with_origins(vec!["*"]))
         })
+        .options("/api/delivery/quote", |_req, _ctx| {
+            Response::empty()?.with_cors(&Cors::default().with_origins(vec!["*"]))
+        })
+        // Правки из админки с If-Match: заголовки CORS добавляются ко всем ответам ниже
+        .options("/api/products/edit/:id", |_req, _ctx| Response::empty())
+        .options("/api/categories/edit/:id", |_req, _ctx| Response::empty())
+        // PATCH требует preflight
+        .options("/api/products/:id", |_req, _ctx| Response::empty())
+        .options("/api/categories/:id", |_req, _ctx| Response::empty())
         .get("/", |_, _| Response::ok("Rust API OK"))
         .get_async("/api/categories", handlers::categories::list_categories)
         .post_async("/api/categories", handlers::categories::create_category)
         .get_async("/api/categories/:id", handlers::categories::get_category)
+        .patch_async("/api/categories/:id", handlers::categories::patch_category)
+        .get_async(
+            "/api/categories/:id/attributes",
+            handlers::attributes::list_attributes,
+        )
+        .post_async(
+            "/api/admin/categories/:id/attributes",
+            handlers::attributes::create_attribute,
+        )
+        .post_async(
+            "/api/admin/attributes/:id",
+            handlers::attributes::update_attribute,
+        )
+        .delete_async(
+            "/api/admin/attributes/:id",
+            handlers::attributes::delete_attribute,
+        )
         .post_async(
             "/api/categories/edit/:id",
             handlers::categories::update_category,
@@ -35,20 +96,138 @@ async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
             "/api/categories/delete",
             handlers::categories::delete_category,
         )
+        .get_async(
+            "/api/products/by-barcode/:code",
+            handlers::products::get_by_barcode,
+        )
+        .get_async("/api/products/facets", handlers::attributes::product_facets)
         .get_async("/api/products/:id", handlers::products::get_product)
+        .patch_async("/api/products/:id", handlers::products::patch_product)
         .post_async("/api/products/edit/:id", handlers::products::update_product)
         .get_async("/api/products", handlers::products::list_products)
         .post_async("/api/products", handlers::products::create_product)
         .post_async("/api/products/delete", handlers::products::delete_product)
+        .post_async("/api/admin/products/bulk", handlers::products::bulk_update)
         .get_async("/api/orders", handlers::orders::list_orders)
+        .get_async("/api/orders/track", handlers::orders::track_order)
+        .get_async("/api/orders/counts", handlers::orders::order_counts)
+        .get_async("/api/orders/:id", handlers::orders::get_order)
+        .post_async("/api/orders/edit/:id", handlers::orders::edit_order)
+        .post_async(
+            "/api/orders/fulfill/:id",
+            handlers::orders::fulfill_order_item,
+        )
         .post_async("/api/orders/delete", handlers::orders::delete_order)
+        .post_async(
+            "/api/admin/products/:id/attributes",
+            handlers::attributes::set_product_attributes,
+        )
         .post_async("/api/cart-items", handlers::products::get_cart_items)
         .post_async("/api/create-order", handlers::orders::create_order) // Создать новый заказ
         .post_async("/api/check-promo", handlers::promo::check_promo)
         .get_async("/api/admin/promos", handlers::promo::list_promos)
         .post_async("/api/admin/promos", handlers::promo::create_promo)
         .delete_async("/api/admin/promos/:id", handlers::promo::delete_promo)
+        .get_async(
+            "/api/admin/inventory/low-stock",
+            handlers::inventory::low_stock,
+        )
+        .get_async(
+            "/api/admin/inventory/movements/:id",
+            handlers::inventory::product_movements,
+        )
+        .post_async(
+            "/api/admin/inventory/receive",
+            handlers::inventory::receive_goods,
+        )
+        .post_async(
+            "/api/admin/inventory/write-off",
+            handlers::inventory::write_off,
+        )
+        .post_async(
+            "/api/admin/inventory/count",
+            handlers::inventory::inventory_count,
+        )
+        .post_async(
+            "/api/admin/import/products",
+            handlers::import::import_products,
+        )
+        .get_async(
+            "/api/admin/import/history",
+            handlers::import::import_history,
+        )
+        .get_async(
+            "/api/admin/export/products",
+            handlers::export::export_products,
+        )
+        .get_async(
+            "/api/admin/export/categories",
+            handlers::export::export_categories,
+        )
+        .get_async("/api/admin/export/orders", handlers::export::export_orders)
+        .get_async("/sitemap.xml", handlers::sitemap::sitemap)
+        .get_async(
+            "/sitemaps/categories.xml",
+            handlers::sitemap::sitemap_categories,
+        )
+        .get_async(
+            "/sitemaps/products/:page",
+            handlers::sitemap::sitemap_products,
+        )
+        .get_async("/feeds/yandex.yml", handlers::feeds::yandex_feed)
+        .get_async("/feeds/google.xml", handlers::feeds::google_feed)
+        .post_async(
+            "/api/admin/feeds/regenerate",
+            handlers::feeds::regenerate_feeds,
+        )
+        .get_async("/api/delivery-zones", handlers::delivery::list_zones)
+        .post_async("/api/delivery/quote", handlers::delivery::quote_delivery)
+        .get_async("/api/delivery-slots", handlers::delivery::available_slots)
+        .get_async("/api/store/status", handlers::store::get_status)
+        .get_async("/api/admin/store/hours", handlers::store::list_hours)
+        .post_async("/api/admin/store/hours", handlers::store::save_hours)
+        .get_async("/api/admin/store/closures", handlers::store::list_closures)
+        .post_async("/api/admin/store/closures", handlers::store::create_closure)
+        .delete_async(
+            "/api/admin/store/closures/:id",
+            handlers::store::delete_closure,
+        )
+        .get_async("/api/admin/delivery-slots", handlers::delivery::list_slots)
+        .post_async("/api/admin/delivery-slots", handlers::delivery::create_slot)
+        .post_async(
+            "/api/admin/delivery-slots/:id",
+            handlers::delivery::update_slot,
+        )
+        .delete_async(
+            "/api/admin/delivery-slots/:id",
+            handlers::delivery::delete_slot,
+        )
+        .post_async("/api/admin/delivery-zones", handlers::delivery::create_zone)
+        .post_async(
+            "/api/admin/delivery-zones/:id",
+            handlers::delivery::update_zone,
+        )
+        .delete_async(
+            "/api/admin/delivery-zones/:id",
+            handlers::delivery::delete_zone,
+        )
         .run(req, env)
         .await?
         .with_cors(&cors)
 }
+
+// Фоновые задачи из очереди (и её dead-letter очереди)
+#[event(queue)]
+async fn queue(batch: MessageBatch<serde_json::Value>, env: Env, _ctx: Context) -> Result<()> {
+    clock::configure(&env);
+    jobs::consume(batch, &env).await
+}
+
+// Плановые задачи (wrangler.toml [triggers]): пересборка товарных фидов
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    clock::configure(&env);
+    if let Err(e) = feeds::regenerate(&env).await {
+        console_error!("feed regeneration failed: {}", e);
+    }
+}
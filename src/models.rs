@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use crate::clock;
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -15,6 +16,38 @@ pub struct Product {
     pub description: Option<String>,
     pub description_kk: Option<String>,
     pub stock: Option<f64>,
+    // Порог "мало на складе"; без него действует LOW_STOCK_THRESHOLD
+    #[serde(default)]
+    pub low_stock_threshold: Option<f64>,
+    // Последний уровень, о котором оповестили (low/out); служебное, наружу не отдаём
+    #[serde(default, skip_serializing)]
+    pub stock_alert: Option<String>,
+    #[serde(default)]
+    pub is_hidden: i32,
+    #[serde(default)]
+    pub sku: Option<String>,
+    // Из product_barcodes; заполняется там, где штрихкоды загружены отдельно
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub barcodes: Option<Vec<String>>,
+    // Вариант (фасовка) товара parent_id: "1 л", "900 г"
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+    #[serde(default)]
+    pub variant_label: Option<String>,
+    #[serde(default)]
+    pub variant_label_kk: Option<String>,
+    // Варианты родительского товара; заполняется в карточке товара
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variants: Option<Vec<Product>>,
+    // Характеристики товара; заполняется в карточке товара
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<Vec<ProductAttribute>>,
+    #[serde(default, serialize_with = "clock::serialize_opt_timestamp")]
+    pub created_at: Option<String>,
+    #[serde(default, serialize_with = "clock::serialize_opt_timestamp")]
+    pub updated_at: Option<String>,
+    #[serde(default = "default_version")]
+    pub version: i32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,6 +59,12 @@ pub struct Category {
     pub name_kk: String,
     pub image: Option<String>,
     pub slug: Option<String>,
+    #[serde(default, serialize_with = "clock::serialize_opt_timestamp")]
+    pub created_at: Option<String>,
+    #[serde(default, serialize_with = "clock::serialize_opt_timestamp")]
+    pub updated_at: Option<String>,
+    #[serde(default = "default_version")]
+    pub version: i32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,6 +78,125 @@ pub struct Order {
     pub items_json: String,
     pub total_price: f64,
     pub status: String,
+    #[serde(serialize_with = "clock::serialize_timestamp")]
+    pub created_at: String,
+    pub order_number: Option<String>,
+    pub promo_code: Option<String>,
+    pub delivery_zone_id: Option<i32>,
+    #[serde(default)]
+    pub delivery_fee: f64,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub delivery_slot_id: Option<i32>,
+    pub delivery_date: Option<String>,
+    #[serde(default)]
+    pub placed_while_closed: i32,
+}
+
+// Позиция заказа из items_json. Остальные поля, которые прислал фронт
+// (картинка, единица измерения и т.п.), сохраняются как есть
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrderItem {
+    #[serde(deserialize_with = "id_from_str_or_number")]
+    pub id: i32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub price: f64,
+    #[serde(default)]
+    pub quantity: f64,
+    // Сборка: "substituted", "partial" или "missing"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fulfillment: Option<String>,
+    // Сколько заказал покупатель, если при сборке количество изменилось
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ordered_quantity: Option<f64>,
+    // Исходный товар, если позицию заменили
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced: Option<ReplacedItem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fulfillment_note: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplacedItem {
+    pub id: i32,
+    pub name: String,
+    pub price: f64,
+    pub quantity: f64,
+}
+
+// Фронт присылает id то строкой, то числом
+fn id_from_str_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse::<i64>().ok()))
+        .unwrap_or(0) as i32)
+}
+
+// Записи до появления колонки version считаем первой версией
+fn default_version() -> i32 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct OrderAuditEntry {
+    pub id: i32,
+    pub action: String,
+    pub changes_json: String,
+    pub actor: Option<String>,
+    #[serde(serialize_with = "clock::serialize_timestamp")]
+    pub created_at: String,
+}
+
+// Запись журнала движения товара
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StockMovementEntry {
+    pub id: i32,
+    pub product_id: i32,
+    pub reason: String,
+    pub delta: f64,
+    pub balance: Option<f64>,
+    pub actor: Option<String>,
+    pub order_id: Option<i32>,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub supplier: Option<String>,
+    #[serde(default)]
+    pub unit_cost: Option<f64>,
+    #[serde(serialize_with = "clock::serialize_timestamp")]
+    pub created_at: String,
+}
+
+// Запись истории импорта товаров
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProductImport {
+    pub id: i32,
+    pub file_name: Option<String>,
+    pub actor: Option<String>,
+    pub dry_run: i32,
+    pub committed: i32,
+    pub match_by: String,
+    pub total_rows: i32,
+    pub created_count: i32,
+    pub updated_count: i32,
+    pub unchanged_count: i32,
+    pub error_count: i32,
+    pub errors_json: String,
+    #[serde(serialize_with = "clock::serialize_timestamp")]
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct OrderStatusChange {
+    pub status: String,
+    pub note: Option<String>,
+    #[serde(serialize_with = "clock::serialize_timestamp")]
     pub created_at: String,
 }
 
@@ -48,4 +206,94 @@ pub struct PromoCode {
     pub code: String,
     pub discount: i32,
     pub is_active: Option<i32>,
+    #[serde(default, serialize_with = "clock::serialize_opt_timestamp")]
+    pub created_at: Option<String>,
+    #[serde(default, serialize_with = "clock::serialize_opt_timestamp")]
+    pub updated_at: Option<String>,
+}
+
+// Характеристика, которую можно задать товарам категории
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CategoryAttribute {
+    pub id: i32,
+    pub category_id: i32,
+    pub code: String,
+    pub name: String,
+    pub name_kk: Option<String>,
+    // text / number / enum / bool
+    #[serde(rename = "type")]
+    pub kind: String,
+    // JSON-массив значений для enum
+    pub options: Option<String>,
+    pub unit: Option<String>,
+    pub sort_order: i32,
+    #[serde(default, serialize_with = "clock::serialize_opt_timestamp")]
+    pub created_at: Option<String>,
+    #[serde(default, serialize_with = "clock::serialize_opt_timestamp")]
+    pub updated_at: Option<String>,
+}
+
+// Значение характеристики в карточке товара
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ProductAttribute {
+    #[serde(skip_serializing)]
+    pub product_id: i32,
+    pub code: String,
+    pub name: String,
+    pub name_kk: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub unit: Option<String>,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct DeliveryZone {
+    pub id: Option<i32>,
+    pub name: String,
+    pub name_kk: Option<String>,
+    // GeoJSON Polygon/MultiPolygon
+    pub polygon: Option<String>,
+    // JSON-массив районов и улиц
+    pub districts: Option<String>,
+    pub delivery_fee: f64,
+    pub free_delivery_from: Option<f64>,
+    pub min_order: f64,
+    pub is_active: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct DeliverySlot {
+    pub id: Option<i32>,
+    // 1 = понедельник ... 7 = воскресенье
+    pub weekday: i32,
+    pub start_time: String,
+    pub end_time: String,
+    pub capacity: i32,
+    // За сколько минут до начала слота закрывается запись
+    pub cutoff_minutes: i32,
+    pub is_active: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct StoreHours {
+    // 1 = понедельник ... 7 = воскресенье
+    pub weekday: i32,
+    pub open_time: Option<String>,
+    pub close_time: Option<String>,
+    pub is_closed: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct StoreClosure {
+    pub id: Option<i32>,
+    pub date: String,
+    pub reason: Option<String>,
+    pub reason_kk: Option<String>,
 }
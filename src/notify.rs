@@ -0,0 +1,435 @@
+use crate::clock;
+use crate::handlers::inventory::StockLevel;
+use crate::models::{Order, OrderItem, Product};
+use std::time::Duration;
+use worker::*;
+
+// Задержки между повторными попытками отправки
+const RETRY_DELAYS_MS: [u64; 2] = [1_000, 4_000];
+
+// Уведомление для магазина: текст для людей и данные для вебхуков
+pub struct Notification {
+    pub event: &'static str,
+    pub order_id: Option<i32>,
+    pub text: String,
+    pub data: serde_json::Value,
+}
+
+// Каналы доставки уведомлений. Новый канал — новый вариант и ветка в deliver()
+pub enum Channel {
+    // POST JSON { event, text, data } на произвольный URL
+    Webhook {
+        url: String,
+        secret: Option<String>,
+    },
+    // sendMessage в Telegram Bot API
+    Telegram {
+        api_url: String,
+        token: String,
+        chat_id: String,
+    },
+}
+
+impl Channel {
+    fn name(&self) -> &'static str {
+        match self {
+            Channel::Webhook { .. } => "webhook",
+            Channel::Telegram { .. } => "telegram",
+        }
+    }
+
+    // Что пишем в журнал (без токенов)
+    fn target(&self) -> String {
+        match self {
+            Channel::Webhook { url, .. } => url.clone(),
+            Channel::Telegram { chat_id, .. } => chat_id.clone(),
+        }
+    }
+
+    // URL, заголовки и тело запроса — без сети, чтобы проверять тестами
+    fn request(&self, notification: &Notification) -> OutgoingRequest {
+        let mut headers = vec![("Content-Type", "application/json".to_string())];
+        match self {
+            Channel::Webhook { url, secret } => {
+                if let Some(secret) = secret {
+                    headers.push(("X-Webhook-Secret", secret.clone()));
+                }
+                OutgoingRequest {
+                    url: url.clone(),
+                    headers,
+                    body: serde_json::json!({
+                        "event": notification.event,
+                        "text": notification.text,
+                        "data": notification.data,
+                    }),
+                }
+            }
+            Channel::Telegram {
+                api_url,
+                token,
+                chat_id,
+            } => OutgoingRequest {
+                url: format!("{}/bot{}/sendMessage", api_url.trim_end_matches('/'), token),
+                headers,
+                body: serde_json::json!({
+                    "chat_id": chat_id,
+                    "text": notification.text,
+                    "parse_mode": "HTML",
+                    "disable_web_page_preview": true,
+                }),
+            },
+        }
+    }
+
+    async fn deliver(&self, notification: &Notification) -> Result<u16> {
+        let outgoing = self.request(notification);
+
+        let headers = Headers::new();
+        for (name, value) in &outgoing.headers {
+            headers.set(name, value)?;
+        }
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post)
+            .with_headers(headers)
+            .with_body(Some(outgoing.body.to_string().into()));
+
+        let request = Request::new_with_init(&outgoing.url, &init)?;
+        let response = Fetch::Request(request).send().await?;
+        Ok(response.status_code())
+    }
+}
+
+// POST-запрос в канал
+struct OutgoingRequest {
+    url: String,
+    headers: Vec<(&'static str, String)>,
+    body: serde_json::Value,
+}
+
+// Каналы из настроек воркера:
+// NOTIFY_WEBHOOK_URLS — URL через запятую, NOTIFY_WEBHOOK_SECRET — заголовок X-Webhook-Secret,
+// TELEGRAM_BOT_TOKEN (секрет) и TELEGRAM_CHAT_ID, TELEGRAM_API_URL — для локальной заглушки
+pub fn channels(env: &Env) -> Vec<Channel> {
+    let var = |name: &str| {
+        env.var(name)
+            .map(|v| v.to_string())
+            .or_else(|_| env.secret(name).map(|v| v.to_string()))
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+
+    let mut channels = Vec::new();
+
+    let secret = var("NOTIFY_WEBHOOK_SECRET");
+    for url in var("NOTIFY_WEBHOOK_URLS").unwrap_or_default().split(',') {
+        let url = url.trim();
+        if !url.is_empty() {
+            channels.push(Channel::Webhook {
+                url: url.to_string(),
+                secret: secret.clone(),
+            });
+        }
+    }
+
+    if let (Some(token), Some(chat_id)) = (var("TELEGRAM_BOT_TOKEN"), var("TELEGRAM_CHAT_ID")) {
+        channels.push(Channel::Telegram {
+            api_url: var("TELEGRAM_API_URL")
+                .unwrap_or_else(|| "https://api.telegram.org".to_string()),
+            token,
+            chat_id,
+        });
+    }
+
+    channels
+}
+
+#[derive(serde::Deserialize)]
+struct Delivered {
+    channel: String,
+    target: String,
+}
+
+// Отправка во все каналы с повторами. Каждая попытка пишется в notification_log.
+// Если канал так и не принял уведомление — ошибка, чтобы очередь повторила задачу;
+// каналы, уже получившие уведомление о заказе, при повторе пропускаются
+pub async fn dispatch(env: &Env, notification: &Notification) -> Result<()> {
+    let d1 = env.d1("akniet_db")?;
+
+    let delivered = match notification.order_id {
+        Some(order_id) => d1
+            .prepare(
+                "SELECT DISTINCT channel, target FROM notification_log
+                 WHERE order_id = ? AND event = ? AND success = 1",
+            )
+            .bind(&[order_id.into(), notification.event.into()])?
+            .all()
+            .await?
+            .results::<Delivered>()?,
+        None => Vec::new(),
+    };
+
+    let mut failed = Vec::new();
+    for channel in channels(env) {
+        let target = channel.target();
+        if delivered
+            .iter()
+            .any(|d| d.channel == channel.name() && d.target == target)
+        {
+            continue;
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let (status, error) = match channel.deliver(notification).await {
+                Ok(status) if (200..300).contains(&status) => (Some(status), None),
+                Ok(status) => (Some(status), Some(format!("HTTP {}", status))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let success = error.is_none();
+
+            let _ = d1
+                .prepare(
+                    "INSERT INTO notification_log (channel, target, event, order_id, attempt, status_code, success, error, created_at)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, datetime('now'))",
+                )
+                .bind(&[
+                    channel.name().into(),
+                    target.clone().into(),
+                    notification.event.into(),
+                    notification.order_id.into(),
+                    attempt.into(),
+                    status.map(|s| s as i32).into(),
+                    (success as i32).into(),
+                    error.clone().into(),
+                ])?
+                .run()
+                .await;
+
+            // Ошибки 4xx (кроме 429) повторять бессмысленно
+            let retryable = !matches!(status, Some(s) if (400..500).contains(&s) && s != 429);
+            if success || !retryable || attempt > RETRY_DELAYS_MS.len() as i32 {
+                if let Some(error) = error {
+                    failed.push(format!("{}: {}", channel.name(), error));
+                }
+                break;
+            }
+            Delay::from(Duration::from_millis(RETRY_DELAYS_MS[attempt as usize - 1])).await;
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(format!(
+            "{} not delivered: {}",
+            notification.event,
+            failed.join("; ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// 12345.0 -> "12 345"
+fn money(value: f64) -> String {
+    let rounded = (value * 100.0).round() / 100.0;
+    let whole = rounded.trunc() as i64;
+    let digits = whole.abs().to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(' ');
+        }
+        grouped.push(c);
+    }
+    if whole < 0 {
+        grouped.insert(0, '-');
+    }
+    let fraction = ((rounded - rounded.trunc()).abs() * 100.0).round() as i64;
+    if fraction > 0 {
+        format!("{},{:02}", grouped, fraction)
+    } else {
+        grouped
+    }
+}
+
+pub fn new_order(order: &Order, items: &[OrderItem]) -> Notification {
+    let number = order.order_number.as_deref().unwrap_or_default();
+    let comment = order.comment.as_deref().unwrap_or_default();
+
+    let mut text = format!(
+        "🛒 <b>Новый заказ {}</b>\n\n👤 {}\n📞 {}\n📍 {}\n",
+        escape_html(number),
+        escape_html(&order.customer_name),
+        escape_html(&order.customer_phone),
+        escape_html(&order.address),
+    );
+    if let Some(date) = &order.delivery_date {
+        text.push_str(&format!("🗓 Доставка: {}\n", escape_html(date)));
+    }
+    if !comment.is_empty() {
+        text.push_str(&format!("💬 {}\n", escape_html(comment)));
+    }
+    if order.placed_while_closed != 0 {
+        text.push_str("⚠️ Оформлен в нерабочее время\n");
+    }
+
+    text.push('\n');
+    for item in items {
+        text.push_str(&format!(
+            "• {} × {} = {} ₸\n",
+            escape_html(&item.name),
+            item.quantity,
+            money(item.price * item.quantity)
+        ));
+    }
+    if order.delivery_fee > 0.0 {
+        text.push_str(&format!("🚚 Доставка: {} ₸\n", money(order.delivery_fee)));
+    }
+    text.push_str(&format!("\n<b>Итого: {} ₸</b>", money(order.total_price)));
+
+    Notification {
+        event: "order.created",
+        order_id: Some(order.id),
+        text,
+        data: serde_json::json!({
+            "id": order.id,
+            "order_number": number,
+            "customer": {
+                "name": order.customer_name,
+                "phone": order.customer_phone,
+                "address": order.address,
+                "comment": comment,
+            },
+            "items": items,
+            "delivery_fee": order.delivery_fee,
+            "delivery_date": order.delivery_date,
+            "total": order.total_price,
+            "placed_while_closed": order.placed_while_closed != 0,
+            "created_at": clock::to_rfc3339(&order.created_at),
+        }),
+    }
+}
+
+pub fn stock_alert(
+    alerts: &[(Product, StockLevel)],
+    threshold: impl Fn(&Product) -> Option<f64>,
+) -> Notification {
+    let mut text = String::from("📦 <b>Остатки на складе</b>\n\n");
+    for (product, level) in alerts {
+        let unit = product.unit.as_deref().unwrap_or_default();
+        let line = match level {
+            StockLevel::Out => format!(
+                "❌ {} (#{}) — закончился\n",
+                escape_html(&product.name),
+                product.id
+            ),
+            StockLevel::Low => format!(
+                "⚠️ {} (#{}) — осталось {} {} (порог {})\n",
+                escape_html(&product.name),
+                product.id,
+                product.stock.unwrap_or_default(),
+                escape_html(unit),
+                threshold(product).unwrap_or_default(),
+            ),
+        };
+        text.push_str(&line);
+    }
+
+    Notification {
+        event: "stock.alert",
+        order_id: None,
+        text,
+        data: serde_json::json!({
+            "products": alerts
+                .iter()
+                .map(|(p, level)| serde_json::json!({
+                    "id": p.id,
+                    "name": p.name,
+                    "stock": p.stock,
+                    "threshold": threshold(p),
+                    "level": level.as_str(),
+                }))
+                .collect::<Vec<_>>(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        Notification {
+            event: "order.created",
+            order_id: Some(7),
+            text: "🛒 <b>Новый заказ A-7</b>".to_string(),
+            data: serde_json::json!({ "id": 7 }),
+        }
+    }
+
+    fn header<'a>(request: &'a OutgoingRequest, name: &str) -> Option<&'a str> {
+        request
+            .headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn webhook_request() {
+        let channel = Channel::Webhook {
+            url: "http://localhost:9000/hook".to_string(),
+            secret: Some("s3cret".to_string()),
+        };
+        let request = channel.request(&notification());
+        assert_eq!(request.url, "http://localhost:9000/hook");
+        assert_eq!(header(&request, "Content-Type"), Some("application/json"));
+        assert_eq!(header(&request, "X-Webhook-Secret"), Some("s3cret"));
+        assert_eq!(request.body["event"], "order.created");
+        assert_eq!(request.body["text"], "🛒 <b>Новый заказ A-7</b>");
+        assert_eq!(request.body["data"]["id"], 7);
+    }
+
+    #[test]
+    fn webhook_without_secret() {
+        let channel = Channel::Webhook {
+            url: "https://example.com/hook".to_string(),
+            secret: None,
+        };
+        let request = channel.request(&notification());
+        assert_eq!(header(&request, "X-Webhook-Secret"), None);
+    }
+
+    #[test]
+    fn telegram_request() {
+        let channel = Channel::Telegram {
+            api_url: "http://localhost:9000/".to_string(),
+            token: "123:abc".to_string(),
+            chat_id: "-100500".to_string(),
+        };
+        let request = channel.request(&notification());
+        assert_eq!(request.url, "http://localhost:9000/bot123:abc/sendMessage");
+        assert_eq!(header(&request, "Content-Type"), Some("application/json"));
+        assert_eq!(request.body["chat_id"], "-100500");
+        assert_eq!(request.body["parse_mode"], "HTML");
+        assert_eq!(request.body["disable_web_page_preview"], true);
+        assert_eq!(request.body["text"], "🛒 <b>Новый заказ A-7</b>");
+        // Токен в журнал не попадает
+        assert_eq!(channel.target(), "-100500");
+    }
+
+    #[test]
+    fn money_groups_thousands() {
+        assert_eq!(money(12345.0), "12 345");
+        assert_eq!(money(1000000.5), "1 000 000,50");
+        assert_eq!(money(-990.0), "-990");
+    }
+}
@@ -0,0 +1,116 @@
+// Частичное обновление (PATCH): в UPDATE попадают только переданные поля.
+// Отсутствующее поле не меняется, null очищает колонку (если она допускает NULL)
+use wasm_bindgen::JsValue;
+
+pub enum FieldKind {
+    // Непустая строка
+    Text,
+    // Строка или null; пустая строка тоже очищает
+    NullableText,
+    // Строка в нижнем регистре (slug)
+    Slug,
+    // Число >= 0
+    Number,
+    // Число >= 0 или null
+    NullableNumber,
+    // id другой записи или null
+    NullableId,
+    // Список строк, хранится как JSON (картинки товара)
+    StringList,
+    // true/false -> 1/0
+    Flag,
+}
+
+// Поля, которые не относятся к колонкам и обрабатываются отдельно
+const SERVICE_FIELDS: [&str; 2] = ["version", "actor"];
+
+pub struct Patch {
+    pub columns: Vec<&'static str>,
+    sets: Vec<String>,
+    pub params: Vec<JsValue>,
+}
+
+impl Patch {
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    // "name = ?, price = ?"
+    pub fn set_clause(&self) -> String {
+        self.sets.join(", ")
+    }
+
+    pub fn value(&self, column: &str) -> Option<&JsValue> {
+        self.columns
+            .iter()
+            .position(|c| *c == column)
+            .map(|i| &self.params[i])
+    }
+}
+
+pub fn build(
+    body: &serde_json::Value,
+    fields: &[(&'static str, FieldKind)],
+) -> Result<Patch, String> {
+    let object = body.as_object().ok_or("Ожидается JSON-объект")?;
+
+    let mut patch = Patch {
+        columns: Vec::new(),
+        sets: Vec::new(),
+        params: Vec::new(),
+    };
+
+    for (key, value) in object {
+        if SERVICE_FIELDS.contains(&key.as_str()) {
+            continue;
+        }
+        let Some((column, kind)) = fields.iter().find(|(name, _)| name == key) else {
+            return Err(format!("Поле {} нельзя изменить", key));
+        };
+
+        let param = field_value(value, kind).ok_or(format!("Неверное значение поля {}", key))?;
+        patch.columns.push(column);
+        patch.sets.push(format!("{} = ?", column));
+        patch.params.push(param);
+    }
+
+    Ok(patch)
+}
+
+fn field_value(value: &serde_json::Value, kind: &FieldKind) -> Option<JsValue> {
+    let text = || value.as_str().map(|s| s.trim().to_string());
+    let number = || value.as_f64().filter(|n| *n >= 0.0);
+
+    match kind {
+        FieldKind::Text => text().filter(|s| !s.is_empty()).map(JsValue::from),
+        FieldKind::Slug => text()
+            .filter(|s| !s.is_empty())
+            .map(|s| JsValue::from(s.to_lowercase())),
+        FieldKind::NullableText if value.is_null() => Some(JsValue::NULL),
+        FieldKind::NullableText => text().map(|s| {
+            if s.is_empty() {
+                JsValue::NULL
+            } else {
+                JsValue::from(s)
+            }
+        }),
+        FieldKind::Number => number().map(JsValue::from),
+        FieldKind::NullableNumber if value.is_null() => Some(JsValue::NULL),
+        FieldKind::NullableNumber => number().map(JsValue::from),
+        FieldKind::NullableId if value.is_null() => Some(JsValue::NULL),
+        FieldKind::NullableId => value
+            .as_i64()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+            .filter(|id| *id > 0)
+            .map(|id| JsValue::from(id as i32)),
+        FieldKind::Flag => value.as_bool().map(|b| JsValue::from(b as i32)),
+        FieldKind::StringList if value.is_null() => Some(JsValue::from("[]")),
+        FieldKind::StringList => {
+            let list = value.as_array()?;
+            if !list.iter().all(|v| v.is_string()) {
+                return None;
+            }
+            Some(JsValue::from(value.to_string()))
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// Адреса страниц витрины. Фиды и sitemap ссылаются на сайт магазина, а не на API
+use crate::models::Product;
+use worker::Env;
+
+const IMAGE_BASE_URL: &str = "https://img.tabys-go.ru";
+
+pub const LANGUAGES: [&str; 2] = ["ru", "kk"];
+
+pub struct Storefront {
+    pub url: String,
+    pub name: String,
+    pub company: String,
+}
+
+impl Storefront {
+    pub fn from_env(env: &Env) -> Self {
+        let var = |name: &str| {
+            env.var(name)
+                .map(|v| v.to_string().trim().to_string())
+                .ok()
+                .filter(|v| !v.is_empty())
+        };
+        let name = var("STORE_NAME").unwrap_or_else(|| "Tabys".to_string());
+        Storefront {
+            url: var("STOREFRONT_URL")
+                .unwrap_or_else(|| "https://tabys-go.ru".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            company: var("STORE_COMPANY").unwrap_or_else(|| name.clone()),
+            name,
+        }
+    }
+
+    // Казахская версия витрины — те же пути с префиксом /kk
+    pub fn localized(&self, path: &str, lang: &str) -> String {
+        match lang {
+            "kk" => format!("{}/kk{}", self.url, path),
+            _ => format!("{}{}", self.url, path),
+        }
+    }
+
+    pub fn product_url(&self, id: i32) -> String {
+        self.localized(&product_path(id), "ru")
+    }
+}
+
+pub fn product_path(id: i32) -> String {
+    format!("/product/{}", id)
+}
+
+pub fn category_path(slug: &str) -> String {
+    format!("/category/{}", slug)
+}
+
+// Картинки товара: JSON-массив ссылок, в старых записях — одна ссылка или имя файла
+pub fn image_urls(product: &Product) -> Vec<String> {
+    let Some(image) = product.image.as_deref().map(str::trim) else {
+        return Vec::new();
+    };
+    let images: Vec<String> = if image.starts_with('[') {
+        serde_json::from_str(image).unwrap_or_default()
+    } else {
+        vec![image.to_string()]
+    };
+    images
+        .into_iter()
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            if url.starts_with("http") {
+                url
+            } else {
+                format!("{}/{}", IMAGE_BASE_URL, url.trim_start_matches('/'))
+            }
+        })
+        .collect()
+}
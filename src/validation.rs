@@ -0,0 +1,347 @@
+use worker::Request;
+
+// Ошибка проверки данных покупателя: текст на русском и казахском
+#[derive(Debug)]
+pub struct ValidationError {
+    pub ru: &'static str,
+    pub kk: &'static str,
+}
+
+impl ValidationError {
+    // Выбираем язык сообщения по заголовку Accept-Language
+    pub fn message(&self, req: &Request) -> &'static str {
+        let lang = req
+            .headers()
+            .get("Accept-Language")
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        if lang.starts_with("kk") {
+            self.kk
+        } else {
+            self.ru
+        }
+    }
+}
+
+// Данные покупателя после проверки
+#[derive(Debug)]
+pub struct Customer {
+    pub name: String,
+    pub phone: String,
+    pub address: String,
+    pub comment: String,
+}
+
+// Приводит казахстанский номер к виду +77XXXXXXXXX.
+// Принимает 8 7xx..., +7 (7xx) ..., 7 7xx... и 7xx... (10 цифр)
+pub fn normalize_phone(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    let (has_plus, rest) = match raw.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, raw),
+    };
+
+    // Кроме цифр допускаем только разделители, которые обычно вводят руками
+    if !rest
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '(' | ')' | '-' | '.'))
+    {
+        return None;
+    }
+
+    let digits: String = rest.chars().filter(|c| c.is_ascii_digit()).collect();
+
+    let national = match digits.len() {
+        11 if digits.starts_with('7') => &digits[1..],
+        11 if digits.starts_with('8') && !has_plus => &digits[1..],
+        10 if !has_plus => digits.as_str(),
+        _ => return None,
+    };
+
+    // Казахстанские мобильные номера начинаются с 7 (700-708, 747, 771, 775-778 и т.д.),
+    // номера на 9, 4, 3 — российские
+    if !national.starts_with('7') {
+        return None;
+    }
+
+    Some(format!("+7{}", national))
+}
+
+// Проверяет блок "customer" из тела заказа
+pub fn validate_customer(customer: &serde_json::Value) -> Result<Customer, ValidationError> {
+    let name = customer["name"].as_str().unwrap_or("").trim().to_string();
+    let phone_raw = customer["phone"].as_str().unwrap_or("");
+    let address = customer["address"]
+        .as_str()
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    let comment = customer["comment"]
+        .as_str()
+        .unwrap_or("")
+        .trim()
+        .to_string();
+
+    if name.is_empty() {
+        return Err(ValidationError {
+            ru: "Укажите имя",
+            kk: "Атыңызды көрсетіңіз",
+        });
+    }
+
+    if phone_raw.trim().is_empty() {
+        return Err(ValidationError {
+            ru: "Укажите номер телефона",
+            kk: "Телефон нөмірін көрсетіңіз",
+        });
+    }
+
+    let phone = normalize_phone(phone_raw).ok_or(ValidationError {
+        ru: "Неверный номер телефона. Укажите казахстанский номер в формате +7 7XX XXX XX XX",
+        kk: "Телефон нөмірі қате. Қазақстандық нөмірді +7 7XX XXX XX XX форматында көрсетіңіз",
+    })?;
+
+    if address.is_empty() {
+        return Err(ValidationError {
+            ru: "Укажите адрес доставки",
+            kk: "Жеткізу мекенжайын көрсетіңіз",
+        });
+    }
+
+    Ok(Customer {
+        name,
+        phone,
+        address,
+        comment,
+    })
+}
+
+// Контрольная цифра GTIN (EAN-8, UPC-A, EAN-13): веса 3 и 1 справа налево
+fn gtin_checksum_ok(digits: &[u32]) -> bool {
+    let Some((check, body)) = digits.split_last() else {
+        return false;
+    };
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+    (10 - sum % 10) % 10 == *check
+}
+
+// Штрихкод EAN-13, UPC-A (12 цифр) или EAN-8 с проверкой контрольной цифры.
+// UPC-A храним как EAN-13 с ведущим нулём: сканеры читают его и так, и так
+pub fn normalize_barcode(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if !raw.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits: Vec<u32> = raw.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !matches!(digits.len(), 8 | 12 | 13) || !gtin_checksum_ok(&digits) {
+        return None;
+    }
+    Some(if digits.len() == 12 {
+        format!("0{}", raw)
+    } else {
+        raw.to_string()
+    })
+}
+
+// Список штрихкодов через запятую, точку с запятой или пробел, без повторов
+pub fn parse_barcodes<'a>(
+    values: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<String>, String> {
+    let mut barcodes: Vec<String> = Vec::new();
+    for value in values
+        .into_iter()
+        .flat_map(|v| v.split([',', ';', ' ', '\n']))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+    {
+        let barcode = normalize_barcode(value).ok_or(format!("Неверный штрихкод: {}", value))?;
+        if !barcodes.contains(&barcode) {
+            barcodes.push(barcode);
+        }
+    }
+    Ok(barcodes)
+}
+
+// Артикул: буквы, цифры и - _ . / длиной до 64 символов, храним в верхнем регистре
+// (уникальность в базе всё равно без учёта регистра)
+pub fn normalize_sku(raw: &str) -> Option<String> {
+    let sku = raw.trim();
+    let valid = !sku.is_empty()
+        && sku.chars().count() <= 64
+        && sku
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    valid.then(|| sku.to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phone_with_country_code() {
+        assert_eq!(
+            normalize_phone("+77011234567").as_deref(),
+            Some("+77011234567")
+        );
+        assert_eq!(
+            normalize_phone("77011234567").as_deref(),
+            Some("+77011234567")
+        );
+    }
+
+    #[test]
+    fn phone_with_trunk_prefix() {
+        assert_eq!(
+            normalize_phone("87011234567").as_deref(),
+            Some("+77011234567")
+        );
+        assert_eq!(
+            normalize_phone("8 701 123 45 67").as_deref(),
+            Some("+77011234567")
+        );
+    }
+
+    #[test]
+    fn phone_without_prefix() {
+        assert_eq!(
+            normalize_phone("7011234567").as_deref(),
+            Some("+77011234567")
+        );
+    }
+
+    #[test]
+    fn phone_formatted() {
+        assert_eq!(
+            normalize_phone(" +7 (701) 123-45-67 ").as_deref(),
+            Some("+77011234567")
+        );
+        assert_eq!(
+            normalize_phone("8.701.123.45.67").as_deref(),
+            Some("+77011234567")
+        );
+    }
+
+    #[test]
+    fn phone_invalid() {
+        // Российский номер, лишние/недостающие цифры, буквы, +8
+        assert_eq!(normalize_phone("+79161234567"), None);
+        assert_eq!(normalize_phone("870112345678"), None);
+        assert_eq!(normalize_phone("701123456"), None);
+        assert_eq!(normalize_phone("+7 701 ABC 45 67"), None);
+        assert_eq!(normalize_phone("+87011234567"), None);
+        assert_eq!(normalize_phone("+7011234567"), None);
+        assert_eq!(normalize_phone(""), None);
+    }
+
+    #[test]
+    fn customer_is_trimmed_and_normalized() {
+        let customer = validate_customer(&serde_json::json!({
+            "name": "  Айгерим ",
+            "phone": "8 (701) 123-45-67",
+            "address": " Абая 1 ",
+        }))
+        .unwrap();
+        assert_eq!(customer.name, "Айгерим");
+        assert_eq!(customer.phone, "+77011234567");
+        assert_eq!(customer.address, "Абая 1");
+        assert_eq!(customer.comment, "");
+    }
+
+    #[test]
+    fn customer_missing_fields() {
+        let error = |value: serde_json::Value| validate_customer(&value).unwrap_err().ru;
+        assert_eq!(
+            error(serde_json::json!({ "phone": "+77011234567", "address": "Абая 1" })),
+            "Укажите имя"
+        );
+        assert_eq!(
+            error(serde_json::json!({ "name": "Айгерим", "address": "Абая 1" })),
+            "Укажите номер телефона"
+        );
+        assert!(error(
+            serde_json::json!({ "name": "Айгерим", "phone": "123", "address": "Абая 1" })
+        )
+        .starts_with("Неверный номер телефона"));
+        assert_eq!(
+            error(
+                serde_json::json!({ "name": "Айгерим", "phone": "+77011234567", "address": " " })
+            ),
+            "Укажите адрес доставки"
+        );
+    }
+
+    #[test]
+    fn valid_ean13() {
+        assert_eq!(
+            normalize_barcode("4006381333931").as_deref(),
+            Some("4006381333931")
+        );
+        assert_eq!(
+            normalize_barcode(" 4870001234560 ").as_deref(),
+            Some("4870001234560")
+        );
+        assert_eq!(normalize_barcode("96385074").as_deref(), Some("96385074"));
+    }
+
+    #[test]
+    fn upc_a_becomes_ean13() {
+        assert_eq!(
+            normalize_barcode("036000291452").as_deref(),
+            Some("0036000291452")
+        );
+        assert_eq!(
+            normalize_barcode("0036000291452").as_deref(),
+            Some("0036000291452")
+        );
+    }
+
+    #[test]
+    fn wrong_check_digit() {
+        assert_eq!(normalize_barcode("4006381333932"), None);
+        assert_eq!(normalize_barcode("036000291453"), None);
+        assert_eq!(normalize_barcode("96385075"), None);
+    }
+
+    #[test]
+    fn barcode_digits_only() {
+        assert_eq!(normalize_barcode("40063813339A1"), None);
+        assert_eq!(normalize_barcode("4006-381333931"), None);
+        assert_eq!(normalize_barcode("١٢٣٤٥٦٧٨"), None);
+        assert_eq!(normalize_barcode(""), None);
+        assert_eq!(normalize_barcode("400638133393"), None);
+    }
+
+    #[test]
+    fn barcode_list_without_duplicates() {
+        let barcodes =
+            parse_barcodes(["4006381333931, 4006381333931", "036000291452;0036000291452"]).unwrap();
+        assert_eq!(barcodes, vec!["4006381333931", "0036000291452"]);
+        assert_eq!(parse_barcodes([" ", ""]).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            parse_barcodes(["4006381333931 4006381333932"]),
+            Err("Неверный штрихкод: 4006381333932".to_string())
+        );
+    }
+
+    #[test]
+    fn sku_trimmed_and_uppercased() {
+        assert_eq!(
+            normalize_sku("  ab-12/x.5_k ").as_deref(),
+            Some("AB-12/X.5_K")
+        );
+        assert_eq!(normalize_sku("мол-01").as_deref(), Some("МОЛ-01"));
+        assert_eq!(normalize_sku("AB 12"), None);
+        assert_eq!(normalize_sku("   "), None);
+        assert_eq!(normalize_sku(&"A".repeat(65)), None);
+        assert_eq!(normalize_sku(&"a".repeat(64)), Some("A".repeat(64)));
+    }
+}
@@ -0,0 +1,53 @@
+// Оптимистичная блокировка для правок из админки.
+// У товара и категории есть version: он отдаётся в JSON и в ETag, а при изменении
+// клиент присылает его обратно в If-Match или в поле version.
+// UPDATE проходит только при совпадении версии и увеличивает её на 1
+use serde::Serialize;
+use worker::*;
+
+pub fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+pub fn with_etag(mut response: Response, version: i32) -> Result<Response> {
+    response.headers_mut().set("ETag", &etag(version))?;
+    Ok(response)
+}
+
+// "3", "\"3\"" и W/"3" -> 3
+fn parse_version(value: &str) -> Option<i32> {
+    let value = value.trim();
+    let value = value.strip_prefix("W/").unwrap_or(value);
+    value.trim_matches('"').parse().ok()
+}
+
+// Версия, с которой клиент начинал правку: заголовок If-Match, иначе поле version
+pub fn expected_version(req: &Request, field: Option<&serde_json::Value>) -> Option<i32> {
+    if let Ok(Some(header)) = req.headers().get("If-Match") {
+        if let Some(version) = parse_version(&header) {
+            return Some(version);
+        }
+    }
+    match field? {
+        serde_json::Value::Number(n) => n.as_i64().map(|v| v as i32),
+        serde_json::Value::String(s) => parse_version(s),
+        _ => None,
+    }
+}
+
+pub fn version_required() -> Result<Response> {
+    Response::error(
+        "Укажите версию записи (заголовок If-Match или поле version)",
+        428,
+    )
+}
+
+// 409 с текущим состоянием записи, чтобы клиент мог показать изменения и повторить
+pub fn conflict<T: Serialize>(current: &T, version: i32) -> Result<Response> {
+    let response = Response::from_json(&serde_json::json!({
+        "error": "Запись уже изменена другим пользователем",
+        "current": current,
+    }))?
+    .with_status(409);
+    with_etag(response, version)
+}